@@ -0,0 +1,1808 @@
+use std::rc::Rc;
+
+use crate::{
+    conditions,
+    error::{ErrorObject, Exception},
+    generator::Generator,
+    list::List,
+    map::{Key, Map},
+    pair::{self, Pair},
+    program::{push, then, Frame, Tail},
+    promise::Promise,
+    stack::Stack,
+    symbol::{Symbol, SymbolMap},
+    value::{Builtin, BuiltinFn, ControlFn, Primitive, Value},
+    vector::Vector,
+};
+
+const BUILTINS: &[(&str, BuiltinFn)] = &[
+    ("+", add),
+    ("-", sub),
+    ("*", mul),
+    ("/", div),
+    ("gt", gt),
+    ("gte", gte),
+    ("lt", lt),
+    ("lte", lte),
+    ("eq", eq),
+    ("not", not),
+    ("list", list),
+    ("cons", cons),
+    ("car", car),
+    ("cdr", cdr),
+    ("pair?", is_pair),
+    ("set-car!", set_car),
+    ("set-cdr!", set_cdr),
+    ("length", length),
+    ("range", range),
+    ("take", take),
+    ("drop", drop),
+    ("zip", zip),
+    ("gensym", gensym),
+    ("error", error),
+    ("raise", raise),
+    ("error?", is_error),
+    ("error-kind", error_kind),
+    ("error-message", error_message),
+    ("error-data", error_data),
+    ("make-condition", make_condition),
+    ("signal", signal),
+    ("invoke-restart", invoke_restart),
+    ("compute-restarts", compute_restarts),
+    ("make-generator", make_generator),
+    ("generator-done?", generator_done),
+    ("make-promise", make_promise),
+    ("promise?", is_promise),
+    ("stream-car", stream_car),
+    ("break", _break),
+    ("continue", _continue),
+    ("hash-map", hash_map),
+    ("hash-get", hash_get),
+    ("hash-set", hash_set),
+    ("hash-remove", hash_remove),
+    ("hash-keys", hash_keys),
+    ("hash-values", hash_values),
+    ("hash-contains?", hash_contains),
+    ("hash-merge", hash_merge),
+    ("hash->list", hash_to_list),
+    ("vector", vector),
+    ("vector?", is_vector),
+    ("vector-ref", vector_ref),
+    ("vector-set!", vector_set),
+    ("vector-length", vector_length),
+    ("vector-push", vector_push),
+    ("vector-slice", vector_slice),
+    ("vector->list", vector_to_list),
+    ("list->vector", list_to_vector),
+    ("conj", conj),
+    ("assoc", assoc),
+    ("char->integer", char_to_integer),
+    ("integer->char", integer_to_char),
+    ("char-upcase", char_upcase),
+    ("char-alphabetic?", is_char_alphabetic),
+    ("char-numeric?", is_char_numeric),
+    ("char-whitespace?", is_char_whitespace),
+    ("string->list", string_to_list),
+    ("list->string", list_to_string),
+    ("keyword?", is_keyword),
+    ("values", values),
+];
+
+/// Builtins that call functions, or capture the continuation, by handing
+/// control back to the evaluator.
+const CONTROL: &[(&str, ControlFn)] = &[
+    ("apply", _apply),
+    ("map", map),
+    ("for-each", for_each),
+    ("filter", filter),
+    ("reduce", reduce),
+    ("fold-left", fold_left),
+    ("fold-right", fold_right),
+    ("any", any),
+    ("every", every),
+    ("find", find),
+    ("sort", sort),
+    ("call/cc", call_cc),
+    ("call-with-current-continuation", call_cc),
+    ("dynamic-wind", dynamic_wind),
+    ("call-with-values", call_with_values),
+    ("next", next),
+    ("yield", _yield),
+    ("force", force),
+    ("stream-cdr", stream_cdr),
+    ("stream-take", stream_take),
+    ("stream-map", stream_map),
+    ("stream-filter", stream_filter),
+];
+
+thread_local! {
+    /// Every builtin by name, so looking one up is a single hash of a symbol.
+    static TABLE: SymbolMap<Value> = BUILTINS
+        .iter()
+        .map(|(name, func)| (*name, Primitive::Value(*func)))
+        .chain(CONTROL.iter().map(|(name, func)| (*name, Primitive::Control(*func))))
+        .map(|(name, func)| (Symbol::new(name), Value::Builtin(Builtin { name, func })))
+        .collect();
+}
+
+pub fn lookup(name: Symbol) -> Option<Value> {
+    TABLE.with(|table| table.get(&name).cloned())
+}
+
+fn arity(name: &str, args: &[Value], n: usize) -> Result<(), Exception> {
+    if args.len() != n {
+        return Err(Exception::new(
+            "arity-error",
+            format!("Incorrect number of arguments for {}", name),
+        ));
+    }
+    Ok(())
+}
+
+fn number(v: &Value) -> Result<f64, Exception> {
+    match v {
+        Value::Number(n) => Ok(*n),
+        _ => Err(Exception::new("type-error", "Operands must be numbers")),
+    }
+}
+
+fn index(v: &Value) -> Result<usize, Exception> {
+    match v {
+        Value::Number(n) if *n >= 0.0 && n.fract() == 0.0 => Ok(*n as usize),
+        _ => Err(Exception::new(
+            "type-error",
+            format!("Expected a non-negative integer, got {}", v),
+        )),
+    }
+}
+
+/// Walks the items of a proper list in place, whether it is made of pairs or
+/// not.
+fn items(v: &Value) -> Result<pair::Items, Exception> {
+    match pair::is_proper(v) {
+        true => Ok(pair::items(v)),
+        false => Err(Exception::new(
+            "type-error",
+            format!("Expected a list, got {}", v),
+        )),
+    }
+}
+
+fn fold_numbers(args: &[Value], op: fn(f64, f64) -> f64) -> Result<Value, Exception> {
+    if args.len() < 2 {
+        return Err(Exception::new(
+            "arity-error",
+            "Insufficient number of arguments",
+        ));
+    }
+    let start = number(&args[0])?;
+    let result = args[1..]
+        .iter()
+        .try_fold(start, |acc, arg| Ok::<_, Exception>(op(acc, number(arg)?)))?;
+    Ok(Value::Number(result))
+}
+
+fn add(args: &[Value]) -> Result<Value, Exception> {
+    fold_numbers(args, |a, b| a + b)
+}
+
+fn sub(args: &[Value]) -> Result<Value, Exception> {
+    fold_numbers(args, |a, b| a - b)
+}
+
+fn mul(args: &[Value]) -> Result<Value, Exception> {
+    fold_numbers(args, |a, b| a * b)
+}
+
+fn div(args: &[Value]) -> Result<Value, Exception> {
+    fold_numbers(args, |a, b| a / b)
+}
+
+fn gt(args: &[Value]) -> Result<Value, Exception> {
+    fold_numbers(args, |a, b| if a > b { 1.0 } else { 0.0 })
+}
+
+fn gte(args: &[Value]) -> Result<Value, Exception> {
+    fold_numbers(args, |a, b| if a >= b { 1.0 } else { 0.0 })
+}
+
+fn lt(args: &[Value]) -> Result<Value, Exception> {
+    fold_numbers(args, |a, b| if a < b { 1.0 } else { 0.0 })
+}
+
+fn lte(args: &[Value]) -> Result<Value, Exception> {
+    fold_numbers(args, |a, b| if a <= b { 1.0 } else { 0.0 })
+}
+
+/// Numbers and characters are equal when their values are, symbols and
+/// keywords when they are the same interned name, which only compares their
+/// ids, and records when they are of the same type with equal fields.
+fn eq(args: &[Value]) -> Result<Value, Exception> {
+    if args.len() < 2 {
+        return Err(Exception::new(
+            "arity-error",
+            "Insufficient number of arguments",
+        ));
+    }
+    Ok(args.windows(2).all(|pair| pair[0] == pair[1]).into())
+}
+
+fn not(args: &[Value]) -> Result<Value, Exception> {
+    arity("not", args, 1)?;
+    if let Value::Number(n) = args[0] {
+        Ok(Value::from(n == 0.0))
+    } else {
+        Err(Exception::new("type-error", "Invalid argument"))
+    }
+}
+
+fn list(args: &[Value]) -> Result<Value, Exception> {
+    Ok(Value::List(args.to_vec().into()))
+}
+
+/// `(cons a b)` makes a pair. It is a list when `b` is one.
+fn cons(args: &[Value]) -> Result<Value, Exception> {
+    arity("cons", args, 2)?;
+    Ok(Value::Pair(Rc::new(Pair::new(
+        args[0].clone(),
+        args[1].clone(),
+    ))))
+}
+
+fn car(args: &[Value]) -> Result<Value, Exception> {
+    arity("car", args, 1)?;
+    match pair::uncons(&args[0]) {
+        Some((car, _)) => Ok(car),
+        None => Err(not_a_pair("car", &args[0])),
+    }
+}
+
+fn cdr(args: &[Value]) -> Result<Value, Exception> {
+    arity("cdr", args, 1)?;
+    match pair::uncons(&args[0]) {
+        Some((_, cdr)) => Ok(cdr),
+        None => Err(not_a_pair("cdr", &args[0])),
+    }
+}
+
+fn not_a_pair(name: &str, v: &Value) -> Exception {
+    match v {
+        Value::List(_) => Exception::new("type-error", format!("{} of empty list", name)),
+        _ => Exception::new("type-error", format!("{} expects a pair, got {}", name, v)),
+    }
+}
+
+fn is_pair(args: &[Value]) -> Result<Value, Exception> {
+    arity("pair?", args, 1)?;
+    Ok(pair::uncons(&args[0]).is_some().into())
+}
+
+/// `(set-car! pair value)` replaces the `car` of a pair made by `cons`.
+fn set_car(args: &[Value]) -> Result<Value, Exception> {
+    arity("set-car!", args, 2)?;
+    *mutable_pair("set-car!", &args[0])?.car.borrow_mut() = args[1].clone();
+    Ok(Value::Nil)
+}
+
+/// `(set-cdr! pair value)` replaces the `cdr` of a pair made by `cons`.
+fn set_cdr(args: &[Value]) -> Result<Value, Exception> {
+    arity("set-cdr!", args, 2)?;
+    *mutable_pair("set-cdr!", &args[0])?.cdr.borrow_mut() = args[1].clone();
+    Ok(Value::Nil)
+}
+
+/// Lists that are not made of pairs share their items, so only pairs can be
+/// changed in place.
+fn mutable_pair<'a>(name: &str, v: &'a Value) -> Result<&'a Pair, Exception> {
+    match v {
+        Value::Pair(p) => Ok(p),
+        _ => Err(Exception::new(
+            "type-error",
+            format!("{} expects a pair made by cons, got {}", name, v),
+        )),
+    }
+}
+
+fn length(args: &[Value]) -> Result<Value, Exception> {
+    arity("length", args, 1)?;
+    Ok(Value::Number(items(&args[0])?.count() as f64))
+}
+
+/// `(apply f a b (list c d))` calls `f` with `a b c d`.
+fn _apply(args: &[Value]) -> Result<Tail, Exception> {
+    let (last, init) = match args {
+        [_, .., last] => (last, &args[1..args.len() - 1]),
+        _ => {
+            return Err(Exception::new(
+                "arity-error",
+                "Insufficient number of arguments",
+            ))
+        }
+    };
+    let mut call_args = init.to_vec();
+    call_args.extend(items(last)?);
+    Ok(Tail::Call(args[0].clone(), call_args))
+}
+
+/// What a builtin that calls a function for every element does after a call.
+enum Flow<S> {
+    Next(S),
+    Stop(Value),
+}
+
+/// Calls `f` once for every row of arguments. The calls go through the evaluator
+/// instead of a nested run of it, so a continuation captured inside `f` can be
+/// resumed after the builtin has moved on.
+struct Iteration<S> {
+    f: Value,
+    rows: Vec<Vec<Value>>,
+    /// The arguments of the call for a row.
+    args: fn(&S, &[Value]) -> Vec<Value>,
+    /// The state after the call for a row returned.
+    next: fn(S, &[Value], Value) -> Flow<S>,
+    finish: fn(S) -> Value,
+}
+
+fn iterate<S: Clone + 'static>(
+    it: Rc<Iteration<S>>,
+    i: usize,
+    state: S,
+) -> Result<Tail, Exception> {
+    let row = match it.rows.get(i) {
+        Some(row) => row,
+        None => return Ok(Tail::Return((it.finish)(state))),
+    };
+    let call = Tail::Call(it.f.clone(), (it.args)(&state, row));
+    Ok(then(call, move |res| {
+        match (it.next)(state.clone(), &it.rows[i], res) {
+            Flow::Next(state) => iterate(it.clone(), i + 1, state),
+            Flow::Stop(val) => Ok(Tail::Return(val)),
+        }
+    }))
+}
+
+/// The i:th element of every list, stopping at the shortest.
+fn map_lists(name: &str, args: &[Value]) -> Result<Vec<Vec<Value>>, Exception> {
+    if args.len() < 2 {
+        return Err(Exception::new(
+            "arity-error",
+            format!("Incorrect number of arguments for {}", name),
+        ));
+    }
+    let lists = args[1..]
+        .iter()
+        .map(items)
+        .collect::<Result<Vec<_>, Exception>>()?;
+    Ok(columns(lists).collect())
+}
+
+/// Takes the next item of every list in turn, until one of them runs out.
+fn columns(mut lists: Vec<pair::Items>) -> impl Iterator<Item = Vec<Value>> {
+    std::iter::from_fn(move || lists.iter_mut().map(Iterator::next).collect())
+}
+
+fn rows(list: &Value) -> Result<Vec<Vec<Value>>, Exception> {
+    Ok(items(list)?.map(|item| vec![item]).collect())
+}
+
+/// The results are kept on a persistent stack, a continuation that resumes an
+/// earlier call must not see the results of the later ones.
+fn collect(results: Stack<Value>) -> Value {
+    let mut items = results.iter().cloned().collect::<Vec<_>>();
+    items.reverse();
+    Value::List(items.into())
+}
+
+fn map(args: &[Value]) -> Result<Tail, Exception> {
+    let it = Iteration {
+        f: args.first().cloned().unwrap_or(Value::Nil),
+        rows: map_lists("map", args)?,
+        args: |_, row| row.to_vec(),
+        next: |results: Stack<Value>, _, res| Flow::Next(results.push(res)),
+        finish: collect,
+    };
+    iterate(Rc::new(it), 0, Stack::new())
+}
+
+fn for_each(args: &[Value]) -> Result<Tail, Exception> {
+    if let [f, Value::Generator(generator)] = args {
+        return for_each_generated(f.clone(), generator.clone());
+    }
+    let it = Iteration {
+        f: args.first().cloned().unwrap_or(Value::Nil),
+        rows: map_lists("for-each", args)?,
+        args: |_, row| row.to_vec(),
+        next: |_, _, _| Flow::Next(()),
+        finish: |_| Value::Nil,
+    };
+    iterate(Rc::new(it), 0, ())
+}
+
+fn filter(args: &[Value]) -> Result<Tail, Exception> {
+    arity("filter", args, 2)?;
+    let it = Iteration {
+        f: args[0].clone(),
+        rows: rows(&args[1])?,
+        args: |_, row| row.to_vec(),
+        next: |kept: Stack<Value>, row, res| match res.is_truthy() {
+            true => Flow::Next(kept.push(row[0].clone())),
+            false => Flow::Next(kept),
+        },
+        finish: collect,
+    };
+    iterate(Rc::new(it), 0, Stack::new())
+}
+
+/// `(reduce f init list)`, or `(reduce f list)` starting from the first element.
+fn reduce(args: &[Value]) -> Result<Tail, Exception> {
+    match args {
+        [f, list] => {
+            let mut list = items(list)?;
+            match list.next() {
+                None => Err(Exception::new(
+                    "type-error",
+                    "reduce of empty list with no initial value",
+                )),
+                Some(first) => fold(f, &first, list.map(|item| vec![item]).collect()),
+            }
+        }
+        [_, _, _] => fold_left(args),
+        _ => Err(Exception::new(
+            "arity-error",
+            "Incorrect number of arguments for reduce",
+        )),
+    }
+}
+
+/// `(fold-left f init list)` computes `(f (f init x1) x2) ...`.
+fn fold_left(args: &[Value]) -> Result<Tail, Exception> {
+    arity("fold-left", args, 3)?;
+    fold(&args[0], &args[1], rows(&args[2])?)
+}
+
+fn fold(f: &Value, init: &Value, rows: Vec<Vec<Value>>) -> Result<Tail, Exception> {
+    let it = Iteration {
+        f: f.clone(),
+        rows,
+        args: |acc: &Value, row| vec![acc.clone(), row[0].clone()],
+        next: |_, _, res| Flow::Next(res),
+        finish: |acc| acc,
+    };
+    iterate(Rc::new(it), 0, init.clone())
+}
+
+/// `(fold-right f init list)` computes `(f x1 (f x2 ... init))`.
+fn fold_right(args: &[Value]) -> Result<Tail, Exception> {
+    arity("fold-right", args, 3)?;
+    let mut rows = rows(&args[2])?;
+    rows.reverse();
+    let it = Iteration {
+        f: args[0].clone(),
+        rows,
+        args: |acc: &Value, row| vec![row[0].clone(), acc.clone()],
+        next: |_, _, res| Flow::Next(res),
+        finish: |acc| acc,
+    };
+    iterate(Rc::new(it), 0, args[1].clone())
+}
+
+/// Returns the first true result of the predicate, or false.
+fn any(args: &[Value]) -> Result<Tail, Exception> {
+    arity("any", args, 2)?;
+    let it = Iteration {
+        f: args[0].clone(),
+        rows: rows(&args[1])?,
+        args: |_, row| row.to_vec(),
+        next: |none: Value, _, res| match res.is_truthy() {
+            true => Flow::Stop(res),
+            false => Flow::Next(none),
+        },
+        finish: |none| none,
+    };
+    iterate(Rc::new(it), 0, Value::from(false))
+}
+
+/// Returns the last result of the predicate if all of them are true, or false.
+fn every(args: &[Value]) -> Result<Tail, Exception> {
+    arity("every", args, 2)?;
+    let it = Iteration {
+        f: args[0].clone(),
+        rows: rows(&args[1])?,
+        args: |_, row| row.to_vec(),
+        next: |_, _, res| match res.is_truthy() {
+            true => Flow::Next(res),
+            false => Flow::Stop(res),
+        },
+        finish: |last| last,
+    };
+    iterate(Rc::new(it), 0, Value::from(true))
+}
+
+fn find(args: &[Value]) -> Result<Tail, Exception> {
+    arity("find", args, 2)?;
+    let it = Iteration {
+        f: args[0].clone(),
+        rows: rows(&args[1])?,
+        args: |_, row| row.to_vec(),
+        next: |none: Value, row, res| match res.is_truthy() {
+            true => Flow::Stop(row[0].clone()),
+            false => Flow::Next(none),
+        },
+        finish: |none| none,
+    };
+    iterate(Rc::new(it), 0, Value::Nil)
+}
+
+/// `(call/cc f)` calls `f` with the current continuation.
+fn call_cc(args: &[Value]) -> Result<Tail, Exception> {
+    arity("call/cc", args, 1)?;
+    Ok(Tail::Capture(args[0].clone()))
+}
+
+/// `(dynamic-wind before thunk after)` calls `thunk` and makes sure `before`
+/// runs whenever control enters it and `after` whenever control leaves it,
+/// also through continuations and errors.
+fn dynamic_wind(args: &[Value]) -> Result<Tail, Exception> {
+    arity("dynamic-wind", args, 3)?;
+    let (before, thunk, after) = (args[0].clone(), args[1].clone(), args[2].clone());
+    Ok(then(Tail::Call(before.clone(), vec![]), move |_| {
+        let wind = Frame::Wind(before.clone(), after.clone());
+        Ok(push(wind, Tail::Call(thunk.clone(), vec![])))
+    }))
+}
+
+/// `(values v ...)` returns its arguments as multiple values. One argument is
+/// returned as it is.
+fn values(args: &[Value]) -> Result<Value, Exception> {
+    match args {
+        [val] => Ok(val.clone()),
+        _ => Ok(Value::Values(args.into())),
+    }
+}
+
+/// `(call-with-values producer consumer)` calls `producer` and then `consumer`
+/// with all of its values as arguments.
+fn call_with_values(args: &[Value]) -> Result<Tail, Exception> {
+    arity("call-with-values", args, 2)?;
+    let (producer, consumer) = (args[0].clone(), args[1].clone());
+    let consume = move |vals: Value| Ok(Tail::Call(consumer.clone(), vals.spread()));
+    Ok(push(
+        Frame::Values(Rc::new(consume)),
+        Tail::Call(producer, vec![]),
+    ))
+}
+
+/// `(range end)`, `(range start end)` or `(range start end step)`.
+fn range(args: &[Value]) -> Result<Value, Exception> {
+    let (start, end, step) = match args {
+        [end] => (0.0, number(end)?, 1.0),
+        [start, end] => (number(start)?, number(end)?, 1.0),
+        [start, end, step] => (number(start)?, number(end)?, number(step)?),
+        _ => {
+            return Err(Exception::new(
+                "arity-error",
+                "Incorrect number of arguments for range",
+            ))
+        }
+    };
+    if step == 0.0 {
+        return Err(Exception::new("type-error", "range step must not be zero"));
+    }
+    let mut result = vec![];
+    let mut n = start;
+    while (step > 0.0 && n < end) || (step < 0.0 && n > end) {
+        result.push(Value::Number(n));
+        n += step;
+    }
+    Ok(Value::List(result.into()))
+}
+
+fn take(args: &[Value]) -> Result<Value, Exception> {
+    arity("take", args, 2)?;
+    let n = index(&args[1])?;
+    Ok(Value::List(items(&args[0])?.take(n).collect()))
+}
+
+/// `(drop list n)` returns the rest of the list after `n` items, sharing it.
+fn drop(args: &[Value]) -> Result<Value, Exception> {
+    arity("drop", args, 2)?;
+    let n = index(&args[1])?;
+    let mut list = items(&args[0])?;
+    for _ in list.by_ref().take(n) {}
+    Ok(list.rest)
+}
+
+fn zip(args: &[Value]) -> Result<Value, Exception> {
+    if args.is_empty() {
+        return Ok(Value::List(List::new()));
+    }
+    let lists = args
+        .iter()
+        .map(items)
+        .collect::<Result<Vec<_>, Exception>>()?;
+    Ok(Value::List(
+        columns(lists).map(|row| Value::List(row.into())).collect(),
+    ))
+}
+
+/// `(sort list less?)` is a stable merge sort using a user supplied comparator.
+/// Like `map`, it calls the comparator through the evaluator, merging runs of
+/// sorted elements bottom up, two at a time.
+fn sort(args: &[Value]) -> Result<Tail, Exception> {
+    arity("sort", args, 2)?;
+    let runs = items(&args[0])?
+        .map(|item| Rc::from(vec![item]))
+        .collect::<Vec<_>>();
+    merge_pass(args[1].clone(), runs.into(), 0, Stack::new())
+}
+
+type Run = Rc<[Value]>;
+
+/// The runs of one pass of a merge sort, and the merge of the k:th and the
+/// next one that is in progress.
+struct Pass {
+    less: Value,
+    runs: Rc<[Run]>,
+    k: usize,
+    /// The runs merged by this pass so far, last first.
+    done: Stack<Run>,
+}
+
+/// Merges the runs from the k:th one on in pairs, after the runs in `done`
+/// that were merged already. Starts over with the merged runs until only one
+/// is left.
+fn merge_pass(less: Value, runs: Rc<[Run]>, k: usize, done: Stack<Run>) -> Result<Tail, Exception> {
+    match &runs[k.min(runs.len())..] {
+        [] => {
+            let mut merged = done.iter().cloned().collect::<Vec<_>>();
+            merged.reverse();
+            match merged.as_slice() {
+                [] => Ok(Tail::Return(Value::List(vec![].into()))),
+                [run] => Ok(Tail::Return(Value::List(run.to_vec().into()))),
+                _ => merge_pass(less, merged.into(), 0, Stack::new()),
+            }
+        }
+        [last] => {
+            let done = done.push(last.clone());
+            merge_pass(less, runs.clone(), k + 1, done)
+        }
+        _ => {
+            let pass = Pass {
+                less,
+                runs,
+                k,
+                done,
+            };
+            merge(Rc::new(pass), 0, 0, Stack::new())
+        }
+    }
+}
+
+/// Merges the k:th and the next run, with the first `i` and `j` of their
+/// elements already in `merged`. The state is never changed in place, a
+/// continuation captured in the comparator can resume the merge any number of
+/// times.
+fn merge(pass: Rc<Pass>, i: usize, j: usize, merged: Stack<Value>) -> Result<Tail, Exception> {
+    let (left, right) = (&pass.runs[pass.k], &pass.runs[pass.k + 1]);
+    if i == left.len() || j == right.len() {
+        let mut run = merged.iter().cloned().collect::<Vec<_>>();
+        run.reverse();
+        run.extend_from_slice(&left[i..]);
+        run.extend_from_slice(&right[j..]);
+        let done = pass.done.push(run.into());
+        return merge_pass(pass.less.clone(), pass.runs.clone(), pass.k + 2, done);
+    }
+    let call = Tail::Call(pass.less.clone(), vec![right[j].clone(), left[i].clone()]);
+    Ok(then(call, move |res| {
+        // Only take from the right when it is strictly less to keep the sort stable.
+        if res.is_truthy() {
+            let item = pass.runs[pass.k + 1][j].clone();
+            merge(pass.clone(), i, j + 1, merged.push(item))
+        } else {
+            let item = pass.runs[pass.k][i].clone();
+            merge(pass.clone(), i + 1, j, merged.push(item))
+        }
+    }))
+}
+
+/// `(gensym)` or `(gensym 'prefix)` returns a fresh symbol, which macros can bind
+/// without capturing the variables of the code they expand into.
+fn gensym(args: &[Value]) -> Result<Value, Exception> {
+    let prefix = match args {
+        [] => "g",
+        [Value::Symbol(s)] => s.name(),
+        _ => return Err(Exception::new("type-error", "Invalid argument")),
+    };
+    Ok(Value::Symbol(Symbol::gensym(prefix)))
+}
+
+/// `(error "message" [data])` or `(error 'kind "message" [data])` raises a new
+/// error value. The kind defaults to `error`.
+fn error(args: &[Value]) -> Result<Value, Exception> {
+    let (kind, rest) = match args {
+        [Value::Symbol(kind), rest @ ..] => (kind.name(), rest),
+        _ => ("error", args),
+    };
+    match rest {
+        [Value::String(message)] => Err(Exception::new(kind, message.as_str())),
+        [Value::String(message), data] => {
+            Err(Exception::with_data(kind, message.as_str(), data.clone()))
+        }
+        _ => Err(Exception::new(
+            "type-error",
+            "error expects an optional kind, a message string and optional data",
+        )),
+    }
+}
+
+/// `(raise value)` raises any value, so a caught error can be raised again.
+fn raise(args: &[Value]) -> Result<Value, Exception> {
+    arity("raise", args, 1)?;
+    Err(Exception::Raise(args[0].clone()))
+}
+
+fn error_object(name: &str, args: &[Value]) -> Result<Rc<ErrorObject>, Exception> {
+    arity(name, args, 1)?;
+    match &args[0] {
+        Value::Error(e) => Ok(e.clone()),
+        other => Err(Exception::new(
+            "type-error",
+            format!("Expected an error, got {}", other),
+        )),
+    }
+}
+
+fn is_error(args: &[Value]) -> Result<Value, Exception> {
+    arity("error?", args, 1)?;
+    Ok(matches!(args[0], Value::Error(_)).into())
+}
+
+fn error_kind(args: &[Value]) -> Result<Value, Exception> {
+    Ok(Value::Symbol(Symbol::new(
+        &error_object("error-kind", args)?.kind,
+    )))
+}
+
+fn error_message(args: &[Value]) -> Result<Value, Exception> {
+    Ok(Value::String(
+        error_object("error-message", args)?.message.clone(),
+    ))
+}
+
+fn error_data(args: &[Value]) -> Result<Value, Exception> {
+    Ok(error_object("error-data", args)?.data.clone())
+}
+
+/// `(make-condition 'kind "message" [data])` builds an error value without
+/// raising it, for use with `signal`.
+fn make_condition(args: &[Value]) -> Result<Value, Exception> {
+    let (kind, message, data) = match args {
+        [Value::Symbol(kind), Value::String(message)] => (kind, message, Value::Nil),
+        [Value::Symbol(kind), Value::String(message), data] => (kind, message, data.clone()),
+        _ => {
+            return Err(Exception::new(
+                "type-error",
+                "make-condition expects a kind, a message string and optional data",
+            ))
+        }
+    };
+    Ok(Value::Error(Rc::new(ErrorObject {
+        kind: kind.to_string(),
+        message: message.clone(),
+        data,
+    })))
+}
+
+/// `(signal condition)` calls the matching handlers and returns `nil` if all of
+/// them decline.
+fn signal(args: &[Value]) -> Result<Value, Exception> {
+    arity("signal", args, 1)?;
+    conditions::signal(&args[0], false)?;
+    Ok(Value::Nil)
+}
+
+/// `(invoke-restart 'name arg ...)` unwinds to the innermost active restart with
+/// that name and runs it with the arguments.
+fn invoke_restart(args: &[Value]) -> Result<Value, Exception> {
+    match args.split_first() {
+        Some((Value::Symbol(name), rest)) => Err(conditions::invoke_restart(*name, rest.to_vec())),
+        _ => Err(Exception::new(
+            "type-error",
+            "invoke-restart expects a restart name",
+        )),
+    }
+}
+
+/// `(compute-restarts)` lists the names of the active restarts, innermost first.
+fn compute_restarts(args: &[Value]) -> Result<Value, Exception> {
+    arity("compute-restarts", args, 0)?;
+    Ok(Value::List(
+        conditions::restart_names()
+            .into_iter()
+            .map(Value::Symbol)
+            .collect(),
+    ))
+}
+
+/// `(make-generator f)` makes a generator that runs `f` when it is first
+/// resumed.
+fn make_generator(args: &[Value]) -> Result<Value, Exception> {
+    arity("make-generator", args, 1)?;
+    Ok(Value::Generator(Rc::new(Generator::new(args[0].clone()))))
+}
+
+fn generator(name: &str, v: &Value) -> Result<Rc<Generator>, Exception> {
+    match v {
+        Value::Generator(generator) => Ok(generator.clone()),
+        _ => Err(Exception::new(
+            "type-error",
+            format!("{} expects a generator, got {}", name, v),
+        )),
+    }
+}
+
+/// `(next generator [default])` resumes the generator and returns the value it
+/// yields. Once its function has returned it returns `default`, or raises a
+/// `generator-exhausted` error without one.
+fn next(args: &[Value]) -> Result<Tail, Exception> {
+    match args {
+        [g] => generator("next", g)?.resume(None),
+        [g, default] => generator("next", g)?.resume(Some(default.clone())),
+        _ => Err(Exception::new(
+            "arity-error",
+            "Incorrect number of arguments for next",
+        )),
+    }
+}
+
+/// `(yield value)` suspends the innermost running generator.
+fn _yield(args: &[Value]) -> Result<Tail, Exception> {
+    arity("yield", args, 1)?;
+    Ok(Tail::Yield(args[0].clone()))
+}
+
+fn generator_done(args: &[Value]) -> Result<Value, Exception> {
+    arity("generator-done?", args, 1)?;
+    Ok(generator("generator-done?", &args[0])?.is_done().into())
+}
+
+/// Calls `f` with every value the generator yields, one at a time.
+fn for_each_generated(f: Value, generator: Rc<Generator>) -> Result<Tail, Exception> {
+    let next = generator.resume(Some(Value::Nil))?;
+    Ok(then(next, move |val| {
+        if generator.is_done() {
+            return Ok(Tail::Return(Value::Nil));
+        }
+        let (f, generator) = (f.clone(), generator.clone());
+        Ok(then(Tail::Call(f.clone(), vec![val]), move |_| {
+            for_each_generated(f.clone(), generator.clone())
+        }))
+    }))
+}
+
+fn hash<'a>(name: &str, v: &'a Value) -> Result<&'a Rc<Map>, Exception> {
+    match v {
+        Value::Map(m) => Ok(m),
+        _ => Err(Exception::new(
+            "type-error",
+            format!("{} expects a map, got {}", name, v),
+        )),
+    }
+}
+
+fn key(name: &str, v: &Value) -> Result<Key, Exception> {
+    Key::new(v).ok_or_else(|| {
+        Exception::new(
+            "type-error",
+            format!("{} expects a hashable key, got {}", name, v),
+        )
+    })
+}
+
+/// `(hash-map k v ...)` makes a map of the keys and values.
+fn hash_map(args: &[Value]) -> Result<Value, Exception> {
+    if !args.len().is_multiple_of(2) {
+        return Err(Exception::new(
+            "arity-error",
+            "hash-map expects keys and values in pairs",
+        ));
+    }
+    let mut map = Map::new();
+    for pair in args.chunks(2) {
+        map = map.insert(key("hash-map", &pair[0])?, pair[1].clone());
+    }
+    Ok(Value::Map(Rc::new(map)))
+}
+
+/// `(hash-get map key [default])` returns the value of `key`, or `default` if
+/// the map has no such key.
+fn hash_get(args: &[Value]) -> Result<Value, Exception> {
+    let default = match args.len() {
+        2 => Value::Nil,
+        3 => args[2].clone(),
+        _ => {
+            return Err(Exception::new(
+                "arity-error",
+                "Incorrect number of arguments for hash-get",
+            ))
+        }
+    };
+    let found = hash("hash-get", &args[0])?.get(&key("hash-get", &args[1])?);
+    Ok(found.cloned().unwrap_or(default))
+}
+
+/// `(hash-set map key value)` returns a map like `map` with `key` set.
+fn hash_set(args: &[Value]) -> Result<Value, Exception> {
+    arity("hash-set", args, 3)?;
+    let map = hash("hash-set", &args[0])?.insert(key("hash-set", &args[1])?, args[2].clone());
+    Ok(Value::Map(Rc::new(map)))
+}
+
+/// `(hash-remove map key)` returns a map like `map` without `key`.
+fn hash_remove(args: &[Value]) -> Result<Value, Exception> {
+    arity("hash-remove", args, 2)?;
+    let map = hash("hash-remove", &args[0])?.remove(&key("hash-remove", &args[1])?);
+    Ok(Value::Map(Rc::new(map)))
+}
+
+fn hash_keys(args: &[Value]) -> Result<Value, Exception> {
+    arity("hash-keys", args, 1)?;
+    let map = hash("hash-keys", &args[0])?;
+    Ok(Value::List(map.iter().map(|(k, _)| k.clone()).collect()))
+}
+
+fn hash_values(args: &[Value]) -> Result<Value, Exception> {
+    arity("hash-values", args, 1)?;
+    let map = hash("hash-values", &args[0])?;
+    Ok(Value::List(map.iter().map(|(_, v)| v.clone()).collect()))
+}
+
+fn hash_contains(args: &[Value]) -> Result<Value, Exception> {
+    arity("hash-contains?", args, 2)?;
+    let map = hash("hash-contains?", &args[0])?;
+    Ok(map.get(&key("hash-contains?", &args[1])?).is_some().into())
+}
+
+/// `(hash-merge map ...)` returns a map with the entries of all the maps. The
+/// value from the last map with a key wins.
+fn hash_merge(args: &[Value]) -> Result<Value, Exception> {
+    let mut merged = match args.first() {
+        Some(first) => hash("hash-merge", first)?.as_ref().clone(),
+        None => Map::new(),
+    };
+    for m in args.iter().skip(1) {
+        for (k, v) in hash("hash-merge", m)?.iter() {
+            merged = merged.insert(key("hash-merge", k)?, v.clone());
+        }
+    }
+    Ok(Value::Map(Rc::new(merged)))
+}
+
+/// `(hash->list map)` returns the entries as a list of `(key value)` lists.
+fn hash_to_list(args: &[Value]) -> Result<Value, Exception> {
+    arity("hash->list", args, 1)?;
+    let map = hash("hash->list", &args[0])?;
+    Ok(Value::List(
+        map.iter()
+            .map(|(k, v)| Value::List(vec![k.clone(), v.clone()].into()))
+            .collect(),
+    ))
+}
+
+fn vector_arg<'a>(name: &str, v: &'a Value) -> Result<&'a Vector<Value>, Exception> {
+    match v {
+        Value::Vector(v) => Ok(v),
+        _ => Err(Exception::new(
+            "type-error",
+            format!("{} expects a vector, got {}", name, v),
+        )),
+    }
+}
+
+/// Checks that `v` is an index below `end`.
+fn in_bounds(name: &str, v: &Value, end: usize) -> Result<usize, Exception> {
+    match index(v)? {
+        i if i < end => Ok(i),
+        _ => Err(Exception::with_data(
+            "index-error",
+            format!("{}: index {} out of bounds", name, v),
+            v.clone(),
+        )),
+    }
+}
+
+fn vector(args: &[Value]) -> Result<Value, Exception> {
+    Ok(new_vector(args.iter().cloned().collect()))
+}
+
+fn new_vector(v: Vector<Value>) -> Value {
+    Value::Vector(v)
+}
+
+fn is_vector(args: &[Value]) -> Result<Value, Exception> {
+    arity("vector?", args, 1)?;
+    Ok(matches!(args[0], Value::Vector(_)).into())
+}
+
+fn vector_ref(args: &[Value]) -> Result<Value, Exception> {
+    arity("vector-ref", args, 2)?;
+    let v = vector_arg("vector-ref", &args[0])?;
+    let i = in_bounds("vector-ref", &args[1], v.len())?;
+    Ok(v.get(i).cloned().unwrap_or(Value::Nil))
+}
+
+/// `(vector-set! vector i value)` returns a vector with the item at `i`
+/// replaced. Vectors are values, so `vector` itself is unchanged.
+fn vector_set(args: &[Value]) -> Result<Value, Exception> {
+    arity("vector-set!", args, 3)?;
+    let v = vector_arg("vector-set!", &args[0])?;
+    let i = in_bounds("vector-set!", &args[1], v.len())?;
+    Ok(new_vector(
+        v.set(i, args[2].clone()).unwrap_or_else(|| v.clone()),
+    ))
+}
+
+fn vector_length(args: &[Value]) -> Result<Value, Exception> {
+    arity("vector-length", args, 1)?;
+    let v = vector_arg("vector-length", &args[0])?;
+    Ok(Value::Number(v.len() as f64))
+}
+
+/// `(vector-push vector value)` returns a vector with `value` added at the
+/// end, sharing the items of `vector`.
+fn vector_push(args: &[Value]) -> Result<Value, Exception> {
+    arity("vector-push", args, 2)?;
+    let v = vector_arg("vector-push", &args[0])?;
+    Ok(new_vector(v.push(args[1].clone())))
+}
+
+/// `(vector-slice vector start [end])` returns a new vector of the items from
+/// `start` up to, but not including, `end`.
+fn vector_slice(args: &[Value]) -> Result<Value, Exception> {
+    if !(2..=3).contains(&args.len()) {
+        return Err(Exception::new(
+            "arity-error",
+            "Incorrect number of arguments for vector-slice",
+        ));
+    }
+    let v = vector_arg("vector-slice", &args[0])?;
+    let start = in_bounds("vector-slice", &args[1], v.len() + 1)?;
+    let end = match args.get(2) {
+        Some(end) => in_bounds("vector-slice", end, v.len() + 1)?,
+        None => v.len(),
+    };
+    if start > end {
+        return Err(Exception::new(
+            "index-error",
+            format!("vector-slice: start {} is after end {}", start, end),
+        ));
+    }
+    Ok(new_vector(
+        v.iter().skip(start).take(end - start).cloned().collect(),
+    ))
+}
+
+fn vector_to_list(args: &[Value]) -> Result<Value, Exception> {
+    arity("vector->list", args, 1)?;
+    let v = vector_arg("vector->list", &args[0])?;
+    Ok(Value::List(v.iter().cloned().collect()))
+}
+
+fn list_to_vector(args: &[Value]) -> Result<Value, Exception> {
+    arity("list->vector", args, 1)?;
+    Ok(new_vector(items(&args[0])?.collect()))
+}
+
+/// `(conj coll x ...)` returns a collection like `coll` with the items added:
+/// in front of a list, at the end of a vector, or as `(key value)` entries of a
+/// map. The vector or map shares most of its structure with `coll`.
+fn conj(args: &[Value]) -> Result<Value, Exception> {
+    let (coll, items) = match args.split_first() {
+        Some(split) => split,
+        None => {
+            return Err(Exception::new(
+                "arity-error",
+                "Incorrect number of arguments for conj",
+            ))
+        }
+    };
+    match coll {
+        // The new items go in front of the list in pairs, which share the
+        // list instead of copying it.
+        Value::List(_) | Value::Pair(_) => Ok(items.iter().fold(coll.clone(), |cdr, car| {
+            Value::Pair(Rc::new(Pair::new(car.clone(), cdr)))
+        })),
+        Value::Vector(v) => {
+            let v = items.iter().fold(v.clone(), |v, x| v.push(x.clone()));
+            Ok(new_vector(v))
+        }
+        Value::Map(m) => {
+            let mut map = m.as_ref().clone();
+            for item in items {
+                match item {
+                    Value::List(entry) if entry.len() == 2 => {
+                        map = map.insert(key("conj", &entry[0])?, entry[1].clone())
+                    }
+                    _ => {
+                        return Err(Exception::new(
+                            "type-error",
+                            format!("conj expects (key value) entries for a map, got {}", item),
+                        ))
+                    }
+                }
+            }
+            Ok(Value::Map(Rc::new(map)))
+        }
+        _ => Err(Exception::new(
+            "type-error",
+            format!("conj expects a collection, got {}", coll),
+        )),
+    }
+}
+
+/// `(assoc coll key value ...)` returns a map like `coll` with the keys set,
+/// or a vector like `coll` with the items at the indices replaced. An index
+/// one past the end adds an item.
+fn assoc(args: &[Value]) -> Result<Value, Exception> {
+    let (coll, pairs) = match args.split_first() {
+        Some((coll, pairs)) if !pairs.is_empty() && pairs.len().is_multiple_of(2) => (coll, pairs),
+        _ => {
+            return Err(Exception::new(
+                "arity-error",
+                "assoc expects a collection and keys and values in pairs",
+            ))
+        }
+    };
+    match coll {
+        Value::Map(m) => {
+            let mut map = m.as_ref().clone();
+            for pair in pairs.chunks(2) {
+                map = map.insert(key("assoc", &pair[0])?, pair[1].clone());
+            }
+            Ok(Value::Map(Rc::new(map)))
+        }
+        Value::Vector(v) => {
+            let mut v = v.clone();
+            for pair in pairs.chunks(2) {
+                let i = in_bounds("assoc", &pair[0], v.len() + 1)?;
+                v = match v.set(i, pair[1].clone()) {
+                    Some(v) => v,
+                    None => v.push(pair[1].clone()),
+                };
+            }
+            Ok(new_vector(v))
+        }
+        _ => Err(Exception::new(
+            "type-error",
+            format!("assoc expects a map or a vector, got {}", coll),
+        )),
+    }
+}
+
+fn char_arg(name: &str, v: &Value) -> Result<char, Exception> {
+    match v {
+        Value::Char(c) => Ok(*c),
+        _ => Err(Exception::new(
+            "type-error",
+            format!("{} expects a character, got {}", name, v),
+        )),
+    }
+}
+
+fn char_to_integer(args: &[Value]) -> Result<Value, Exception> {
+    arity("char->integer", args, 1)?;
+    Ok(Value::Number(
+        char_arg("char->integer", &args[0])? as u32 as f64
+    ))
+}
+
+fn integer_to_char(args: &[Value]) -> Result<Value, Exception> {
+    arity("integer->char", args, 1)?;
+    u32::try_from(index(&args[0])?)
+        .ok()
+        .and_then(char::from_u32)
+        .map(Value::Char)
+        .ok_or_else(|| {
+            Exception::new(
+                "type-error",
+                format!("{} is not the code of a character", args[0]),
+            )
+        })
+}
+
+/// `(char-upcase c)` is the upper case of `c`, or `c` itself if that is not
+/// a single character.
+fn char_upcase(args: &[Value]) -> Result<Value, Exception> {
+    arity("char-upcase", args, 1)?;
+    let c = char_arg("char-upcase", &args[0])?;
+    let mut upper = c.to_uppercase();
+    Ok(Value::Char(match (upper.next(), upper.next()) {
+        (Some(u), None) => u,
+        _ => c,
+    }))
+}
+
+fn is_char_alphabetic(args: &[Value]) -> Result<Value, Exception> {
+    arity("char-alphabetic?", args, 1)?;
+    Ok(char_arg("char-alphabetic?", &args[0])?
+        .is_alphabetic()
+        .into())
+}
+
+fn is_char_numeric(args: &[Value]) -> Result<Value, Exception> {
+    arity("char-numeric?", args, 1)?;
+    Ok(char_arg("char-numeric?", &args[0])?.is_numeric().into())
+}
+
+fn is_char_whitespace(args: &[Value]) -> Result<Value, Exception> {
+    arity("char-whitespace?", args, 1)?;
+    Ok(char_arg("char-whitespace?", &args[0])?
+        .is_whitespace()
+        .into())
+}
+
+fn string_to_list(args: &[Value]) -> Result<Value, Exception> {
+    arity("string->list", args, 1)?;
+    match &args[0] {
+        Value::String(s) => Ok(Value::List(s.chars().map(Value::Char).collect())),
+        v => Err(Exception::new(
+            "type-error",
+            format!("string->list expects a string, got {}", v),
+        )),
+    }
+}
+
+fn is_keyword(args: &[Value]) -> Result<Value, Exception> {
+    arity("keyword?", args, 1)?;
+    Ok(matches!(args[0], Value::Keyword(_)).into())
+}
+
+fn list_to_string(args: &[Value]) -> Result<Value, Exception> {
+    arity("list->string", args, 1)?;
+    items(&args[0])?
+        .map(|c| char_arg("list->string", &c))
+        .collect::<Result<String, _>>()
+        .map(Value::String)
+}
+
+/// `(break [value])` leaves the innermost loop, which returns `value`.
+fn _break(args: &[Value]) -> Result<Value, Exception> {
+    match args {
+        [] => Err(Exception::Break(Value::Nil)),
+        [val] => Err(Exception::Break(val.clone())),
+        _ => Err(Exception::new(
+            "arity-error",
+            "Incorrect number of arguments for break",
+        )),
+    }
+}
+
+/// `(continue)` skips the rest of the body of the innermost loop.
+fn _continue(args: &[Value]) -> Result<Value, Exception> {
+    arity("continue", args, 0)?;
+    Err(Exception::Continue)
+}
+
+/// `(force promise)` returns the value of the promise, computing it the first
+/// time. Anything else is returned as it is.
+fn force(args: &[Value]) -> Result<Tail, Exception> {
+    arity("force", args, 1)?;
+    match &args[0] {
+        Value::Promise(promise) => promise.force(),
+        val => Ok(Tail::Return(val.clone())),
+    }
+}
+
+/// `(make-promise value)` makes a promise that is already forced to `value`.
+fn make_promise(args: &[Value]) -> Result<Value, Exception> {
+    arity("make-promise", args, 1)?;
+    match &args[0] {
+        Value::Promise(_) => Ok(args[0].clone()),
+        val => Ok(Value::Promise(Rc::new(Promise::forced(val.clone())))),
+    }
+}
+
+fn is_promise(args: &[Value]) -> Result<Value, Exception> {
+    arity("promise?", args, 1)?;
+    Ok(matches!(args[0], Value::Promise(_)).into())
+}
+
+/// Splits a stream into its head and the promise of its tail, or returns `None`
+/// for the empty stream.
+fn stream(name: &str, v: &Value) -> Result<Option<(Value, Rc<Promise>)>, Exception> {
+    match v {
+        Value::Nil => Ok(None),
+        Value::List(l) => match l.as_slice() {
+            [] => Ok(None),
+            [head, Value::Promise(tail)] => Ok(Some((head.clone(), tail.clone()))),
+            _ => Err(Exception::new(
+                "type-error",
+                format!("{} expects a stream, got {}", name, v),
+            )),
+        },
+        _ => Err(Exception::new(
+            "type-error",
+            format!("{} expects a stream, got {}", name, v),
+        )),
+    }
+}
+
+fn nonempty_stream(name: &str, v: &Value) -> Result<(Value, Rc<Promise>), Exception> {
+    stream(name, v)?
+        .ok_or_else(|| Exception::new("type-error", format!("{} of empty stream", name)))
+}
+
+fn stream_car(args: &[Value]) -> Result<Value, Exception> {
+    arity("stream-car", args, 1)?;
+    Ok(nonempty_stream("stream-car", &args[0])?.0)
+}
+
+fn stream_cdr(args: &[Value]) -> Result<Tail, Exception> {
+    arity("stream-cdr", args, 1)?;
+    nonempty_stream("stream-cdr", &args[0])?.1.force()
+}
+
+/// `(stream-take stream n)` returns a list of the first `n` elements of the
+/// stream, or all of them if it is shorter.
+fn stream_take(args: &[Value]) -> Result<Tail, Exception> {
+    arity("stream-take", args, 2)?;
+    let n = match &args[1] {
+        Value::Number(n) if *n >= 0.0 => *n as usize,
+        v => {
+            return Err(Exception::new(
+                "type-error",
+                format!("stream-take expects a count, got {}", v),
+            ))
+        }
+    };
+    take_from(args[0].clone(), n, Stack::new())
+}
+
+fn take_from(s: Value, n: usize, taken: Stack<Value>) -> Result<Tail, Exception> {
+    let (head, tail) = match stream("stream-take", &s)? {
+        Some(next) if n > 0 => next,
+        _ => return Ok(Tail::Return(collect(taken))),
+    };
+    let taken = taken.push(head);
+    Ok(then(tail.force()?, move |rest| {
+        take_from(rest, n - 1, taken.clone())
+    }))
+}
+
+/// `(stream-map f stream)` is the stream of `f` applied to each element. The
+/// tail is only mapped when it is forced.
+fn stream_map(args: &[Value]) -> Result<Tail, Exception> {
+    arity("stream-map", args, 2)?;
+    let f = args[0].clone();
+    let (head, tail) = match stream("stream-map", &args[1])? {
+        Some(next) => next,
+        None => return Ok(Tail::Return(Value::List(List::new()))),
+    };
+    Ok(then(Tail::Call(f.clone(), vec![head]), move |mapped| {
+        let (f, tail) = (f.clone(), tail.clone());
+        let rest = Promise::delay(move || {
+            let f = f.clone();
+            Ok(then(tail.force()?, move |rest| {
+                stream_map(&[f.clone(), rest])
+            }))
+        });
+        Ok(Tail::Return(Value::List(
+            vec![mapped, Value::Promise(Rc::new(rest))].into(),
+        )))
+    }))
+}
+
+/// `(stream-filter pred stream)` is the stream of the elements that satisfy
+/// `pred`. Finding the next one forces the stream up to it.
+fn stream_filter(args: &[Value]) -> Result<Tail, Exception> {
+    arity("stream-filter", args, 2)?;
+    let pred = args[0].clone();
+    let (head, tail) = match stream("stream-filter", &args[1])? {
+        Some(next) => next,
+        None => return Ok(Tail::Return(Value::List(List::new()))),
+    };
+    let test = Tail::Call(pred.clone(), vec![head.clone()]);
+    Ok(then(test, move |keep| {
+        let (pred, tail) = (pred.clone(), tail.clone());
+        let rest = move || {
+            let pred = pred.clone();
+            Ok(then(tail.force()?, move |rest| {
+                stream_filter(&[pred.clone(), rest])
+            }))
+        };
+        if keep.is_truthy() {
+            let rest = Value::Promise(Rc::new(Promise::delay(rest)));
+            Ok(Tail::Return(Value::List(vec![head.clone(), rest].into())))
+        } else {
+            rest()
+        }
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+
+    use crate::{environment::Environment, program::evaluate};
+
+    use super::*;
+
+    fn eval(source: &str) -> Result<Value, Exception> {
+        let mut env = Rc::new(RefCell::new(Environment::new()));
+        evaluate(source, &mut env)
+    }
+
+    fn numbers(ns: &[f64]) -> Value {
+        Value::List(ns.iter().map(|n| Value::Number(*n)).collect())
+    }
+
+    #[test]
+    fn test_builtin_as_value() {
+        assert_eq!(eval("(apply + 1 (list 2 3))").unwrap(), Value::Number(6.0));
+    }
+
+    #[test]
+    fn test_map_multiple_lists() {
+        let res = eval("(map + (list 1 2 3) (list 10 20))").unwrap();
+        assert_eq!(res, numbers(&[11.0, 22.0]));
+    }
+
+    #[test]
+    fn test_map_lambda() {
+        let res = eval("(map (fn (x) (* x x)) (range 1 4))").unwrap();
+        assert_eq!(res, numbers(&[1.0, 4.0, 9.0]));
+    }
+
+    #[test]
+    fn test_filter() {
+        let res = eval("(filter (fn (x) (gt x 2)) (range 5))").unwrap();
+        assert_eq!(res, numbers(&[3.0, 4.0]));
+    }
+
+    #[test]
+    fn test_reduce_and_folds() {
+        assert_eq!(eval("(reduce + (range 5))").unwrap(), Value::Number(10.0));
+        assert_eq!(
+            eval("(reduce + 10 (range 5))").unwrap(),
+            Value::Number(20.0)
+        );
+        assert_eq!(
+            eval("(fold-left - 0 (list 1 2 3))").unwrap(),
+            Value::Number(-6.0)
+        );
+        assert_eq!(
+            eval("(fold-right - 0 (list 1 2 3))").unwrap(),
+            Value::Number(2.0)
+        );
+        assert!(eval("(reduce + (list))").is_err());
+    }
+
+    #[test]
+    fn test_any_every_find() {
+        assert_eq!(
+            eval("(any (fn (x) (gt x 2)) (range 5))").unwrap(),
+            Value::Number(1.0)
+        );
+        assert_eq!(
+            eval("(every (fn (x) (gt x 2)) (range 5))").unwrap(),
+            Value::Number(0.0)
+        );
+        assert_eq!(
+            eval("(every (fn (x) (gt x 2)) (list))").unwrap(),
+            Value::Number(1.0)
+        );
+        assert_eq!(
+            eval("(find (fn (x) (gt x 2)) (range 5))").unwrap(),
+            Value::Number(3.0)
+        );
+        assert_eq!(
+            eval("(find (fn (x) (gt x 9)) (range 5))").unwrap(),
+            Value::Nil
+        );
+    }
+
+    #[test]
+    fn test_range_take_drop() {
+        assert_eq!(
+            eval("(range 10 0 -3)").unwrap(),
+            numbers(&[10.0, 7.0, 4.0, 1.0])
+        );
+        assert_eq!(eval("(take (range 5) 2)").unwrap(), numbers(&[0.0, 1.0]));
+        assert_eq!(eval("(drop (range 5) 3)").unwrap(), numbers(&[3.0, 4.0]));
+        assert_eq!(eval("(take (range 2) 5)").unwrap(), numbers(&[0.0, 1.0]));
+        match eval("(range 1 5 0)") {
+            Err(Exception::Raise(Value::Error(e))) => assert_eq!(e.kind, "type-error"),
+            other => panic!("expected a type error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_zip() {
+        let res = eval("(zip (list 1 2) (list 3 4 5))").unwrap();
+        assert_eq!(
+            res,
+            Value::List(vec![numbers(&[1.0, 3.0]), numbers(&[2.0, 4.0])].into())
+        );
+    }
+
+    #[test]
+    fn test_sort() {
+        let res = eval("(sort (list 3 1 2) lt)").unwrap();
+        assert_eq!(res, numbers(&[1.0, 2.0, 3.0]));
+        let res = eval("(sort (list 3 1 2) (fn (a b) (gt a b)))").unwrap();
+        assert_eq!(res, numbers(&[3.0, 2.0, 1.0]));
+        let res = eval("(sort (list 5 3 9 1 4 8 2 7 6) lt)").unwrap();
+        assert_eq!(res, numbers(&[1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0, 8.0, 9.0]));
+        assert_eq!(eval("(sort '() lt)").unwrap().to_string(), "()");
+    }
+
+    #[test]
+    fn test_stable_sort() {
+        let res = eval(
+            "(map cdr (sort (list (list 1 1) (list 0 2) (list 1 3) (list 0 4))
+                            (fn (a b) (lt (car a) (car b)))))",
+        )
+        .unwrap();
+        assert_eq!(
+            res,
+            Value::List(
+                vec![
+                    numbers(&[2.0]),
+                    numbers(&[4.0]),
+                    numbers(&[1.0]),
+                    numbers(&[3.0])
+                ]
+                .into()
+            )
+        );
+    }
+
+    #[test]
+    fn test_function_in_list() {
+        let res = eval("((car (list (fn (x) (+ x 1)))) 1)").unwrap();
+        assert_eq!(res, Value::Number(2.0));
+    }
+
+    #[test]
+    fn test_hash_map() {
+        let res = eval(
+            "(let ((m {'b 1 \"a\" (+ 1 1)}))
+               (list (hash-get m 'b) (hash-get m \"a\") (hash-get m 'c 0)
+                     (hash-keys (hash-set m 'c 3)) (hash-values (hash-remove m 'b))
+                     (hash-contains? m 'b) (hash-contains? m 'c)))",
+        )
+        .unwrap();
+        assert_eq!(
+            res,
+            Value::List(
+                vec![
+                    Value::Number(1.0),
+                    Value::Number(2.0),
+                    Value::Number(0.0),
+                    Value::List(
+                        vec![
+                            Value::Symbol(Symbol::new("b")),
+                            Value::String("a".to_string()),
+                            Value::Symbol(Symbol::new("c")),
+                        ]
+                        .into()
+                    ),
+                    numbers(&[2.0]),
+                    Value::Number(1.0),
+                    Value::Number(0.0),
+                ]
+                .into()
+            )
+        );
+    }
+
+    #[test]
+    fn test_hash_merge() {
+        let res = eval("(hash->list (hash-merge {1 1 2 2} (hash-map 2 20 3 30)))").unwrap();
+        assert_eq!(
+            res,
+            Value::List(
+                vec![
+                    numbers(&[1.0, 1.0]),
+                    numbers(&[2.0, 20.0]),
+                    numbers(&[3.0, 30.0]),
+                ]
+                .into()
+            )
+        );
+        assert_eq!(eval("{1 (list 2 3)}").unwrap().to_string(), "{1 (2 3)}");
+        assert_eq!(eval("{1 2 3 4}").unwrap(), eval("{3 4 1 2}").unwrap());
+        assert!(eval("(hash-set {} (fn () 1) 1)").is_err());
+    }
+
+    #[test]
+    fn test_vector() {
+        let res = eval(
+            "(let ((v [1 (+ 1 1) 3]))
+               (set! v (vector-push (vector-set! v 0 10) 4))
+               (list (vector-ref v 0) (vector-length v) (vector->list (vector-slice v 1 3))
+                     (vector->list (vector-slice v 2)) (vector? v) (vector? (list 1))))",
+        )
+        .unwrap();
+        assert_eq!(
+            res,
+            Value::List(
+                vec![
+                    Value::Number(10.0),
+                    Value::Number(4.0),
+                    numbers(&[2.0, 3.0]),
+                    numbers(&[3.0, 4.0]),
+                    Value::Number(1.0),
+                    Value::Number(0.0),
+                ]
+                .into()
+            )
+        );
+        assert_eq!(
+            eval("(list->vector (list 1 2))").unwrap().to_string(),
+            "[1 2]"
+        );
+        let res = eval("(let ((v [1 2])) (list (vector-set! v 0 9) (vector-push v 3) v))").unwrap();
+        assert_eq!(res.to_string(), "([9 2] [1 2 3] [1 2])");
+    }
+
+    #[test]
+    fn test_vector_bounds() {
+        for source in [
+            "(vector-ref [1 2] 2)",
+            "(vector-set! [] 0 1)",
+            "(vector-slice [1 2] 3)",
+            "(vector-slice [1 2] 2 1)",
+        ] {
+            match eval(source) {
+                Err(Exception::Raise(Value::Error(e))) => assert_eq!(e.kind, "index-error"),
+                other => panic!("expected index-error from {}, got {:?}", source, other),
+            }
+        }
+        assert!(eval("(vector-ref [1 2] -1)").is_err());
+    }
+
+    #[test]
+    fn test_conj_assoc() {
+        let res = eval(
+            "(let ((v [1 2]) (m {'a 1}))
+               (list (conj (list 2 3) 1 0) (conj v 3 4) v (assoc v 0 10 2 30)
+                     (conj m (list 'b 2)) (assoc m 'a 5) m))",
+        )
+        .unwrap();
+        assert_eq!(
+            res.to_string(),
+            "((0 1 2 3) [1 2 3 4] [1 2] [10 2 30] {a 1 b 2} {a 5} {a 1})"
+        );
+        assert!(eval("(assoc [1] 2 0)").is_err());
+        assert!(eval("(conj {} 1)").is_err());
+        let list = Value::List(vec![Value::Number(2.0), Value::Number(3.0)].into());
+        match conj(&[list.clone(), Value::Number(1.0)]).unwrap() {
+            Value::Pair(p) => match (&*p.cdr.borrow(), &list) {
+                (Value::List(tail), Value::List(l)) => {
+                    assert_eq!(tail.as_slice().as_ptr(), l.as_slice().as_ptr())
+                }
+                other => panic!("expected the list as the tail, got {:?}", other),
+            },
+            other => panic!("expected a pair, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_builtins_walk_pairs() {
+        let res = eval(
+            "(let ((l (conj (conj '(3) 2) 1)))
+               (list (length l) (map (fn (x) (* x 10)) l) (take l 2) (drop l 1) (zip l '(a b))
+                     (apply + l) (list->vector l) (reduce + l) (filter (fn (x) (gt x 1)) l)
+                     (sort l gt)))",
+        )
+        .unwrap();
+        assert_eq!(
+            res.to_string(),
+            "(3 (10 20 30) (1 2) (2 3) ((1 a) (2 b)) 6 [1 2 3] 6 (2 3) (3 2 1))"
+        );
+        assert!(eval("(length (cons 1 2))").is_err());
+        let list = eval("(conj '(3) 2 1)").unwrap();
+        match drop(&[list.clone(), Value::Number(1.0)]).unwrap() {
+            Value::Pair(rest) => match &list {
+                Value::Pair(p) => {
+                    assert!(matches!(&*p.cdr.borrow(), Value::Pair(cdr) if Rc::ptr_eq(cdr, &rest)))
+                }
+                other => panic!("expected a pair, got {:?}", other),
+            },
+            other => panic!("expected the shared rest, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_large_collections() {
+        let res = eval(
+            "(let ((v []) (m {}))
+               (dotimes (i 5000)
+                 (set! v (conj v i))
+                 (set! m (assoc m i (* i i))))
+               (list (vector-ref v 4321) (hash-get m 4321) (vector-length v)
+                     (length (hash-keys m))))",
+        )
+        .unwrap();
+        assert_eq!(res, numbers(&[4321.0, 18671041.0, 5000.0, 5000.0]));
+    }
+
+    #[test]
+    fn test_pairs() {
+        let res = eval(
+            "(let ((p (cons 1 2)) (l (cons 1 (cons 2 3))) (q (list 1 2)))
+               (set-car! p 'a)
+               (set-cdr! (cdr l) '(4))
+               (list p l (pair? p) (pair? '()) (pair? q) (cdr (cons 1 2))
+                     (conj (cons 2 '()) 1) '(a . b) '(a b . c)))",
+        )
+        .unwrap();
+        assert_eq!(
+            res.to_string(),
+            "((a . 2) (1 2 4) 1 0 1 2 (1 2) (a . b) (a b . c))"
+        );
+        assert_eq!(
+            eval("(length (cons 1 (cons 2 '())))").unwrap(),
+            Value::Number(2.0)
+        );
+        assert_eq!(eval("(cons 1 '(2))").unwrap(), numbers(&[1.0, 2.0]));
+        assert!(eval("(set-car! '(1 2) 0)").is_err());
+        assert!(eval("(car 1)").is_err());
+        assert!(eval("(length (cons 1 2))").is_err());
+    }
+
+    #[test]
+    fn test_chars() {
+        let res = eval(
+            "(list (char->integer #\\A) (integer->char 97) (char-upcase #\\a) (char-upcase #\\1)
+                   (char-alphabetic? #\\a) (char-numeric? #\\7) (char-numeric? #\\a)
+                   (char-whitespace? #\\space) (char-whitespace? #\\newline) #\\x41
+                   (string->list \"hi!\") (list->string (list #\\o #\\k)) #\\( #\\tab)",
+        )
+        .unwrap();
+        assert_eq!(
+            res.to_string(),
+            "(65 #\\a #\\A #\\1 1 1 0 1 1 #\\A (#\\h #\\i #\\!) \"ok\" #\\( #\\tab)"
+        );
+        assert_eq!(
+            eval("(hash-get {#\\a 1} #\\a)").unwrap(),
+            Value::Number(1.0)
+        );
+        assert!(eval("(integer->char -1)").is_err());
+        assert!(eval("(list->string (list 1))").is_err());
+        assert!(eval("(char->integer \"a\")").is_err());
+        let res = eval("(list (eq #\\a #\\a) (eq #\\a #\\b) (eq #\\a 'a))").unwrap();
+        assert_eq!(res, numbers(&[1.0, 0.0, 0.0]));
+    }
+
+    #[test]
+    fn test_keywords() {
+        let res = eval(
+            "(let ((opts {:size 3 :color 'red}))
+               (list :size (hash-get opts :size) (keyword? :size) (keyword? 'size)
+                     (hash-get {:size 1 'size 2} 'size) (hash-keys opts)
+                     (eq :size :size) (eq :size :color) (eq :size 'size)))",
+        )
+        .unwrap();
+        assert_eq!(res.to_string(), "(:size 3 1 0 2 (:size :color) 1 0 0)");
+    }
+
+    #[test]
+    fn test_eq_symbols() {
+        let res =
+            eval("(list (eq 'a 'a) (eq 'a 'b) (eq 'a 1) (eq (car '(x)) 'x) (eq 1 1))").unwrap();
+        assert_eq!(res, numbers(&[1.0, 0.0, 0.0, 1.0, 1.0]));
+    }
+
+    #[test]
+    fn test_eq_compares_every_pair() {
+        let res = eval(
+            "(list (eq \"a\" \"a\") (eq \"a\" \"b\") (eq '(1 (2)) '(1 (2))) (eq '(1) '(2))
+                   (eq (cons 1 '(2)) '(1 2)) (eq 2 2 2) (eq 2 2 1) (eq 'a 'a 'b) (eq 1 \"1\"))",
+        )
+        .unwrap();
+        assert_eq!(res, numbers(&[1.0, 0.0, 1.0, 0.0, 1.0, 1.0, 0.0, 0.0, 0.0]));
+        assert!(eval("(eq 1)").is_err());
+    }
+}
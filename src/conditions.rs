@@ -0,0 +1,159 @@
+use std::{
+    cell::{Cell, RefCell},
+    rc::Rc,
+};
+
+use crate::{error::Exception, program::apply, symbol::Symbol, value::Value};
+
+/// Lets the user pick one of the active restarts, by its index in `names`, when
+/// an error is not handled. Returning `None` lets the error unwind as usual.
+pub type Debugger = Rc<dyn Fn(&Value, &[Symbol]) -> Option<(usize, Vec<Value>)>>;
+
+#[derive(Clone)]
+enum Handler {
+    /// The handlers established by one `handler-bind`, as kind and function.
+    Bind(Vec<(Symbol, Value)>),
+    /// A `try` with a `catch` clause, which stops signaling and lets the
+    /// condition unwind to it.
+    Catch,
+}
+
+#[derive(Clone)]
+struct Restart {
+    name: Symbol,
+    id: usize,
+}
+
+/// The handlers and restarts in effect. Entering a form that establishes new
+/// ones replaces the state, and the evaluator puts the old one back when the
+/// form is left, or when a continuation from outside of it is invoked.
+#[derive(Clone, Default)]
+pub struct State {
+    handlers: Rc<Vec<Handler>>,
+    restarts: Rc<Vec<Restart>>,
+}
+
+thread_local! {
+    static STATE: RefCell<State> = RefCell::new(State::default());
+    static NEXT_RESTART: Cell<usize> = const { Cell::new(0) };
+    static DEBUGGER: RefCell<Option<Debugger>> = const { RefCell::new(None) };
+}
+
+pub fn current() -> State {
+    STATE.with(|s| s.borrow().clone())
+}
+
+pub fn restore(state: State) {
+    STATE.with(|s| *s.borrow_mut() = state);
+}
+
+pub fn set_debugger(debugger: Debugger) {
+    DEBUGGER.with(|d| *d.borrow_mut() = Some(debugger));
+}
+
+/// Calls the handlers for `condition`, innermost first, before anything is
+/// unwound. A handler declines by returning, or takes over by raising or
+/// invoking a restart. Each handler runs with only the handlers outside its own
+/// `handler-bind` active.
+pub fn signal(condition: &Value, is_error: bool) -> Result<(), Exception> {
+    let state = current();
+    for (i, handler) in state.handlers.iter().enumerate().rev() {
+        let cluster = match handler {
+            Handler::Bind(cluster) => cluster,
+            Handler::Catch => return Err(Exception::Unwind(condition.clone())),
+        };
+        for (kind, f) in cluster {
+            if matches(*kind, condition) {
+                restore(State {
+                    handlers: Rc::new(state.handlers[..i].to_vec()),
+                    restarts: state.restarts.clone(),
+                });
+                let res = apply(f, std::slice::from_ref(condition));
+                restore(state.clone());
+                res?;
+            }
+        }
+    }
+    if is_error {
+        debug(condition)?;
+    }
+    Ok(())
+}
+
+/// `condition` matches every value, `error` every error value, and any other
+/// kind only errors of that kind.
+fn matches(kind: Symbol, condition: &Value) -> bool {
+    match condition {
+        _ if kind == "condition" => true,
+        Value::Error(e) => kind == "error" || kind == e.kind.as_str(),
+        _ => false,
+    }
+}
+
+fn debug(condition: &Value) -> Result<(), Exception> {
+    let names = restart_names();
+    let debugger = DEBUGGER.with(|d| d.borrow().clone());
+    match debugger {
+        Some(debugger) if !names.is_empty() => match debugger(condition, &names) {
+            Some((i, args)) if i < names.len() => Err(invoke_restart(names[i], args)),
+            _ => Ok(()),
+        },
+        _ => Ok(()),
+    }
+}
+
+/// Establishes a `handler-bind` cluster of handlers.
+pub fn bind_handlers(handlers: Vec<(Symbol, Value)>) {
+    push_handler(Handler::Bind(handlers));
+}
+
+/// Establishes a `try` that catches.
+pub fn bind_catch() {
+    push_handler(Handler::Catch);
+}
+
+fn push_handler(handler: Handler) {
+    STATE.with(|s| Rc::make_mut(&mut s.borrow_mut().handlers).push(handler));
+}
+
+/// Establishes restarts named `names`. Returns the id of the first one, the ids
+/// of the rest follow it in order.
+pub fn bind_restarts(names: &[Symbol]) -> usize {
+    let first = NEXT_RESTART.with(|n| {
+        let first = n.get();
+        n.set(first + names.len());
+        first
+    });
+    STATE.with(|s| {
+        let mut state = s.borrow_mut();
+        let restarts = Rc::make_mut(&mut state.restarts);
+        for (i, name) in names.iter().enumerate() {
+            restarts.push(Restart {
+                name: *name,
+                id: first + i,
+            });
+        }
+    });
+    first
+}
+
+/// The names of the active restarts, innermost first.
+pub fn restart_names() -> Vec<Symbol> {
+    STATE.with(|s| s.borrow().restarts.iter().rev().map(|r| r.name).collect())
+}
+
+/// Unwinds to the innermost active restart called `name`.
+pub fn invoke_restart(name: Symbol, args: Vec<Value>) -> Exception {
+    let id = STATE.with(|s| {
+        s.borrow()
+            .restarts
+            .iter()
+            .rev()
+            .find(|r| r.name == name)
+            .map(|r| r.id)
+    });
+    match id {
+        Some(id) => Exception::Restart(id, args),
+        None => Exception::new("control-error", format!("No active restart named {}", name)),
+    }
+}
@@ -1,15 +1,38 @@
-use std::{cell::RefCell, collections::HashMap, rc::Rc};
+use std::{cell::RefCell, fmt, rc::Rc};
 
-use crate::value::Value;
+use crate::{
+    error::Exception,
+    symbol::{Symbol, SymbolMap},
+    value::Value,
+};
 
-#[derive(Debug, Default, PartialEq)]
+#[derive(Default)]
 pub struct Environment {
     parent: Option<Rc<RefCell<Environment>>>,
-    vars: HashMap<String, Value>,
+    vars: SymbolMap<Value>,
 }
 
 pub type Env = Rc<RefCell<Environment>>;
 
+// Closures hold on to the environment they were created in, which in turn
+// usually holds the closure itself. Comparing or printing environments by
+// content would never terminate, so they are compared by identity and only
+// their names are printed.
+impl PartialEq for Environment {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+impl fmt::Debug for Environment {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Environment")
+            .field("vars", &self.vars.keys().collect::<Vec<_>>())
+            .field("parent", &self.parent.is_some())
+            .finish()
+    }
+}
+
 impl Environment {
     pub fn new() -> Self {
         Default::default()
@@ -17,22 +40,43 @@ impl Environment {
 
     pub fn extend(parent: Env) -> Self {
         Self {
-            vars: HashMap::new(),
+            vars: SymbolMap::default(),
             parent: Some(parent),
         }
     }
 
-    pub fn get(&self, name: &str) -> Option<Value> {
-        match self.vars.get(name) {
-            Some(value) => Some(value.clone()),
-            None => self
-                .parent
-                .as_ref()
-                .and_then(|o| o.borrow().get(name).clone()),
+    /// Looks `name` up from this scope outwards. An alias that no scope binds
+    /// is a free identifier of a `syntax-rules` template. Macros are defined
+    /// and expanded at the top level, so it means its original there.
+    pub fn get(&self, name: Symbol) -> Option<Value> {
+        match (self.vars.get(&name), &self.parent) {
+            (Some(value), _) => Some(value.clone()),
+            (None, Some(parent)) => parent.borrow().get(name),
+            (None, None) if name.is_alias() => self.vars.get(&name.original()).cloned(),
+            (None, None) => None,
         }
     }
 
-    pub fn set(&mut self, name: &str, val: Value) {
-        self.vars.insert(name.to_string(), val);
+    pub fn set(&mut self, name: Symbol, val: Value) {
+        self.vars.insert(name, val);
+    }
+
+    /// Updates an existing binding in the scope it was defined in, rather than
+    /// shadowing it in the innermost one.
+    pub fn assign(&mut self, name: Symbol, val: Value) -> Result<(), Exception> {
+        match self.vars.get_mut(&name) {
+            Some(var) => {
+                *var = val;
+                Ok(())
+            }
+            None => match &self.parent {
+                Some(parent) => parent.borrow_mut().assign(name, val),
+                None if name.is_alias() => self.assign(name.original(), val),
+                None => Err(Exception::new(
+                    "unbound-variable",
+                    format!("Cannot set unbound symbol {}", name),
+                )),
+            },
+        }
     }
 }
@@ -0,0 +1,67 @@
+use std::{error::Error, fmt, rc::Rc};
+
+use crate::{program::Continuation, value::Value};
+
+/// The payload of `Value::Error`. The kind is a short name such as
+/// `type-error` that handlers can dispatch on, the data is any extra value the
+/// code raising the error wants to pass along.
+#[derive(Debug, PartialEq)]
+pub struct ErrorObject {
+    pub kind: String,
+    pub message: String,
+    pub data: Value,
+}
+
+/// Why evaluation is unwinding.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Exception {
+    /// A value was raised. The interpreter and `error` raise a `Value::Error`,
+    /// but `raise` can raise any value. The evaluator signals it to the
+    /// handlers once the step that raised it has returned.
+    Raise(Value),
+    /// A raised value that has been signaled, unwinding to the innermost `try`
+    /// that catches it. It is a `Raise` again once it gets out of evaluation.
+    Unwind(Value),
+    /// `invoke-restart` is unwinding to the `restart-case` that established
+    /// the restart with this id.
+    Restart(usize, Vec<Value>),
+    /// A continuation was invoked from a nested evaluation, such as a macro
+    /// expander or a condition handler, and is unwinding to the evaluation it
+    /// was captured in.
+    Throw(Rc<Continuation>, Value),
+    /// `break` is leaving the innermost loop with the value.
+    Break(Value),
+    /// `continue` is going on with the next iteration of the innermost loop.
+    Continue,
+}
+
+impl Exception {
+    pub fn new(kind: &str, message: impl Into<String>) -> Self {
+        Exception::with_data(kind, message, Value::Nil)
+    }
+
+    pub fn with_data(kind: &str, message: impl Into<String>, data: Value) -> Self {
+        Exception::Raise(Value::Error(Rc::new(ErrorObject {
+            kind: kind.to_string(),
+            message: message.into(),
+            data,
+        })))
+    }
+}
+
+impl Error for Exception {}
+
+impl fmt::Display for Exception {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Exception::Raise(Value::Error(e)) | Exception::Unwind(Value::Error(e)) => {
+                write!(f, "{}: {}", e.kind, e.message)
+            }
+            Exception::Raise(other) | Exception::Unwind(other) => write!(f, "uncaught {}", other),
+            Exception::Restart(..) => write!(f, "restart invoked outside of its restart-case"),
+            Exception::Throw(..) => write!(f, "continuation invoked outside of its extent"),
+            Exception::Break(_) => write!(f, "break outside of a loop"),
+            Exception::Continue => write!(f, "continue outside of a loop"),
+        }
+    }
+}
@@ -0,0 +1,88 @@
+use std::{cell::RefCell, fmt, rc::Rc};
+
+use crate::{
+    conditions,
+    error::Exception,
+    program::{push, reinstate, Continuation, Frame, Tail},
+    value::Value,
+};
+
+/// A coroutine made by `make-generator`. Its function runs until it calls
+/// `yield`, and the next call to `next` resumes it from there.
+pub struct Generator {
+    state: RefCell<State>,
+}
+
+enum State {
+    Start(Value),
+    Suspended(Rc<Continuation>),
+    Running,
+    Done,
+}
+
+impl Generator {
+    pub fn new(func: Value) -> Self {
+        Generator {
+            state: RefCell::new(State::Start(func)),
+        }
+    }
+
+    pub fn is_done(&self) -> bool {
+        matches!(*self.state.borrow(), State::Done)
+    }
+
+    /// Runs the generator until it yields the next value. Once it has returned
+    /// the result is `default`, or an error if there is none.
+    pub fn resume(self: &Rc<Self>, default: Option<Value>) -> Result<Tail, Exception> {
+        let state = self.state.replace(State::Running);
+        let frame = Frame::Generator(self.clone(), default.clone());
+        let tail = match state {
+            State::Start(func) => Tail::Call(func, vec![]),
+            State::Suspended(k) => reinstate(&k, Value::Nil),
+            State::Running => {
+                return Err(Exception::new(
+                    "control-error",
+                    "The generator is already running",
+                ))
+            }
+            State::Done => {
+                self.state.replace(State::Done);
+                return exhausted(default);
+            }
+        };
+        Ok(push(
+            Frame::Dynamic(conditions::current()),
+            push(frame, tail),
+        ))
+    }
+
+    pub fn suspend(&self, k: Continuation) {
+        self.state.replace(State::Suspended(Rc::new(k)));
+    }
+
+    pub fn finish(&self) {
+        self.state.replace(State::Done);
+    }
+}
+
+pub fn exhausted(default: Option<Value>) -> Result<Tail, Exception> {
+    match default {
+        Some(val) => Ok(Tail::Return(val)),
+        None => Err(Exception::new(
+            "generator-exhausted",
+            "The generator has no more values",
+        )),
+    }
+}
+
+impl PartialEq for Generator {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+impl fmt::Debug for Generator {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Generator")
+    }
+}
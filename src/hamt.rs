@@ -0,0 +1,299 @@
+use std::{
+    collections::hash_map::DefaultHasher,
+    hash::{Hash, Hasher},
+    rc::Rc,
+};
+
+const BITS: u32 = 5;
+const MASK: u64 = (1 << BITS) - 1;
+
+/// A persistent hash map, a hash array mapped trie. Each level of the trie
+/// uses five bits of the hash to pick a child, and only keeps the children that
+/// are there. Inserting or removing a key copies the path to it and shares the
+/// rest of the trie with the old map.
+pub struct Hamt<K, V> {
+    root: Rc<Node<K, V>>,
+    len: usize,
+}
+
+enum Node<K, V> {
+    Branch(u32, Vec<Entry<K, V>>),
+    /// Keys whose hashes are equal in all 64 bits.
+    Collision(u64, Vec<(K, V)>),
+}
+
+enum Entry<K, V> {
+    Leaf(u64, K, V),
+    Node(Rc<Node<K, V>>),
+}
+
+impl<K: Clone, V: Clone> Clone for Entry<K, V> {
+    fn clone(&self) -> Self {
+        match self {
+            Entry::Leaf(hash, key, val) => Entry::Leaf(*hash, key.clone(), val.clone()),
+            Entry::Node(node) => Entry::Node(node.clone()),
+        }
+    }
+}
+
+fn hash_of<K: Hash>(key: &K) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    key.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// The bit for `hash` in the bitmap of a branch at `shift`, and the position
+/// of its child among the children that are there.
+fn slot(bitmap: u32, hash: u64, shift: u32) -> (u32, usize) {
+    let bit = 1 << ((hash >> shift) & MASK);
+    (bit, (bitmap & (bit - 1)).count_ones() as usize)
+}
+
+impl<K: Hash + Eq + Clone, V: Clone> Hamt<K, V> {
+    pub fn new() -> Self {
+        Hamt {
+            root: Rc::new(Node::Branch(0, vec![])),
+            len: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn get(&self, key: &K) -> Option<&V> {
+        let hash = hash_of(key);
+        let mut node = &self.root;
+        let mut shift = 0;
+        loop {
+            match node.as_ref() {
+                Node::Branch(bitmap, children) => {
+                    let (bit, i) = slot(*bitmap, hash, shift);
+                    if bitmap & bit == 0 {
+                        return None;
+                    }
+                    match &children[i] {
+                        Entry::Leaf(_, k, v) => return (k == key).then_some(v),
+                        Entry::Node(child) => node = child,
+                    }
+                }
+                Node::Collision(_, entries) => {
+                    return entries.iter().find(|(k, _)| k == key).map(|(_, v)| v)
+                }
+            }
+            shift += BITS;
+        }
+    }
+
+    #[must_use]
+    pub fn insert(&self, key: K, val: V) -> Self {
+        let hash = hash_of(&key);
+        let (root, added) = insert(&self.root, hash, 0, key, val);
+        Hamt {
+            root,
+            len: self.len + added as usize,
+        }
+    }
+
+    #[must_use]
+    pub fn remove(&self, key: &K) -> Self {
+        match remove(&self.root, hash_of(key), 0, key) {
+            Some(root) => Hamt {
+                root,
+                len: self.len - 1,
+            },
+            None => self.clone(),
+        }
+    }
+}
+
+/// Returns the node with the key inserted, and whether it is a new key.
+fn insert<K: Eq + Clone, V: Clone>(
+    node: &Rc<Node<K, V>>,
+    hash: u64,
+    shift: u32,
+    key: K,
+    val: V,
+) -> (Rc<Node<K, V>>, bool) {
+    match node.as_ref() {
+        Node::Branch(bitmap, children) => {
+            let (bit, i) = slot(*bitmap, hash, shift);
+            let mut children = children.clone();
+            if bitmap & bit == 0 {
+                children.insert(i, Entry::Leaf(hash, key, val));
+                return (Rc::new(Node::Branch(bitmap | bit, children)), true);
+            }
+            let added = match &children[i] {
+                Entry::Leaf(_, k, _) if *k == key => {
+                    children[i] = Entry::Leaf(hash, key, val);
+                    false
+                }
+                Entry::Leaf(h, k, v) => {
+                    let node = pair(shift + BITS, (*h, k.clone(), v.clone()), (hash, key, val));
+                    children[i] = Entry::Node(node);
+                    true
+                }
+                Entry::Node(child) => {
+                    let (child, added) = insert(child, hash, shift + BITS, key, val);
+                    children[i] = Entry::Node(child);
+                    added
+                }
+            };
+            (Rc::new(Node::Branch(*bitmap, children)), added)
+        }
+        Node::Collision(h, entries) if *h == hash => {
+            let mut entries = entries.clone();
+            let added = match entries.iter().position(|(k, _)| *k == key) {
+                Some(i) => {
+                    entries[i] = (key, val);
+                    false
+                }
+                None => {
+                    entries.push((key, val));
+                    true
+                }
+            };
+            (Rc::new(Node::Collision(hash, entries)), added)
+        }
+        // A key with another hash ends up next to a collision node, so the
+        // collision node moves one level down.
+        Node::Collision(h, _) => {
+            let (bit, _) = slot(0, *h, shift);
+            let branch = Rc::new(Node::Branch(bit, vec![Entry::Node(node.clone())]));
+            insert(&branch, hash, shift, key, val)
+        }
+    }
+}
+
+/// A node with two keys that were in the same slot one level up.
+fn pair<K: Eq + Clone, V: Clone>(
+    shift: u32,
+    (h1, k1, v1): (u64, K, V),
+    (h2, k2, v2): (u64, K, V),
+) -> Rc<Node<K, V>> {
+    if h1 == h2 {
+        return Rc::new(Node::Collision(h1, vec![(k1, v1), (k2, v2)]));
+    }
+    let (bit1, _) = slot(0, h1, shift);
+    let (bit2, _) = slot(0, h2, shift);
+    let node = if bit1 == bit2 {
+        Node::Branch(
+            bit1,
+            vec![Entry::Node(pair(shift + BITS, (h1, k1, v1), (h2, k2, v2)))],
+        )
+    } else if bit1 < bit2 {
+        Node::Branch(
+            bit1 | bit2,
+            vec![Entry::Leaf(h1, k1, v1), Entry::Leaf(h2, k2, v2)],
+        )
+    } else {
+        Node::Branch(
+            bit1 | bit2,
+            vec![Entry::Leaf(h2, k2, v2), Entry::Leaf(h1, k1, v1)],
+        )
+    };
+    Rc::new(node)
+}
+
+/// Returns the node without the key, or `None` if the key is not there.
+fn remove<K: Eq + Clone, V: Clone>(
+    node: &Rc<Node<K, V>>,
+    hash: u64,
+    shift: u32,
+    key: &K,
+) -> Option<Rc<Node<K, V>>> {
+    match node.as_ref() {
+        Node::Branch(bitmap, children) => {
+            let (bit, i) = slot(*bitmap, hash, shift);
+            if bitmap & bit == 0 {
+                return None;
+            }
+            let mut children = children.clone();
+            let mut bitmap = *bitmap;
+            match &children[i] {
+                Entry::Leaf(_, k, _) if k == key => {
+                    children.remove(i);
+                    bitmap &= !bit;
+                }
+                Entry::Leaf(..) => return None,
+                Entry::Node(child) => {
+                    let child = remove(child, hash, shift + BITS, key)?;
+                    match child.as_ref() {
+                        Node::Branch(0, _) => {
+                            children.remove(i);
+                            bitmap &= !bit;
+                        }
+                        _ => children[i] = Entry::Node(child),
+                    }
+                }
+            }
+            Some(Rc::new(Node::Branch(bitmap, children)))
+        }
+        Node::Collision(h, entries) => {
+            let i = entries.iter().position(|(k, _)| k == key)?;
+            let mut entries = entries.clone();
+            entries.remove(i);
+            if entries.is_empty() {
+                Some(Rc::new(Node::Branch(0, vec![])))
+            } else {
+                Some(Rc::new(Node::Collision(*h, entries)))
+            }
+        }
+    }
+}
+
+impl<K, V> Clone for Hamt<K, V> {
+    fn clone(&self) -> Self {
+        Hamt {
+            root: self.root.clone(),
+            len: self.len,
+        }
+    }
+}
+
+impl<K: Hash + Eq + Clone, V: Clone> Default for Hamt<K, V> {
+    fn default() -> Self {
+        Hamt::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_insert_and_remove() {
+        let n = 5000;
+        let map = (0..n).fold(Hamt::new(), |map, i| map.insert(i, i * 2));
+        assert_eq!(map.len(), n);
+        assert!((0..n).all(|i| map.get(&i) == Some(&(i * 2))));
+        let smaller = (0..n).step_by(2).fold(map.clone(), |map, i| map.remove(&i));
+        assert_eq!(smaller.len(), n / 2);
+        assert_eq!(smaller.get(&2), None);
+        assert_eq!(smaller.get(&3), Some(&6));
+        assert_eq!(map.get(&2), Some(&4));
+        assert_eq!(map.insert(1, 0).len(), n);
+        assert_eq!(map.remove(&n).len(), n);
+    }
+
+    /// A key that hashes the same as every other key.
+    #[derive(Clone, PartialEq, Eq)]
+    struct Clash(u32);
+
+    impl Hash for Clash {
+        fn hash<H: Hasher>(&self, state: &mut H) {
+            0.hash(state)
+        }
+    }
+
+    #[test]
+    fn test_collisions() {
+        let map = (0..3).fold(Hamt::new(), |map, i| map.insert(Clash(i), i));
+        assert_eq!(map.len(), 3);
+        assert_eq!(map.get(&Clash(1)), Some(&1));
+        let map = map.remove(&Clash(1)).insert(Clash(0), 10);
+        assert_eq!(map.len(), 2);
+        assert_eq!(map.get(&Clash(1)), None);
+        assert_eq!(map.get(&Clash(0)), Some(&10));
+    }
+}
@@ -0,0 +1,79 @@
+use std::{
+    cell::RefCell,
+    collections::HashSet,
+    fmt,
+    hash::{Hash, Hasher},
+    rc::Rc,
+};
+
+thread_local! {
+    static KEYWORDS: RefCell<HashSet<Rc<str>>> = RefCell::new(HashSet::new());
+}
+
+/// A name written `:name` that evaluates to itself. Keywords are interned, all
+/// keywords with the same name share one string, so comparing two of them only
+/// compares pointers.
+#[derive(Clone)]
+pub struct Keyword(Rc<str>);
+
+impl Keyword {
+    /// The keyword for `name`, which does not include the colon.
+    pub fn new(name: &str) -> Self {
+        KEYWORDS.with(|keywords| {
+            let mut keywords = keywords.borrow_mut();
+            match keywords.get(name) {
+                Some(interned) => Keyword(interned.clone()),
+                None => {
+                    let interned: Rc<str> = name.into();
+                    keywords.insert(interned.clone());
+                    Keyword(interned)
+                }
+            }
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.0
+    }
+}
+
+impl PartialEq for Keyword {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for Keyword {}
+
+impl Hash for Keyword {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.0.hash(state)
+    }
+}
+
+impl fmt::Display for Keyword {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, ":{}", self.0)
+    }
+}
+
+impl fmt::Debug for Keyword {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Keyword({})", self.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_interned() {
+        let a = Keyword::new("size");
+        let b = Keyword::new(&String::from("size"));
+        assert!(Rc::ptr_eq(&a.0, &b.0));
+        assert_eq!(a, b);
+        assert_ne!(a, Keyword::new("color"));
+        assert_eq!(a.to_string(), ":size");
+    }
+}
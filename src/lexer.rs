@@ -1,14 +1,24 @@
 use regex::Regex;
 use std::error::Error;
 use std::fmt;
-use std::str::FromStr;
 
 #[derive(Clone, Debug, PartialEq)]
 pub enum Token {
     Number(f64),
     Symbol(String),
+    String(String),
+    Char(char),
+    Keyword(String),
     LParen,
     RParen,
+    LBrace,
+    RBrace,
+    LBracket,
+    RBracket,
+    Quote,
+    Quasiquote,
+    Unquote,
+    UnquoteSplicing,
 }
 
 impl fmt::Display for Token {
@@ -16,22 +26,71 @@ impl fmt::Display for Token {
         match self {
             Token::Number(n) => write!(f, "{}", n),
             Token::Symbol(s) => write!(f, "{}", s),
+            Token::String(s) => write!(f, "{:?}", s),
+            Token::Char(c) => write!(f, "{}", char_literal(*c)),
+            Token::Keyword(s) => write!(f, ":{}", s),
             Token::LParen => write!(f, "("),
             Token::RParen => write!(f, ")"),
+            Token::LBrace => write!(f, "{{"),
+            Token::RBrace => write!(f, "}}"),
+            Token::LBracket => write!(f, "["),
+            Token::RBracket => write!(f, "]"),
+            Token::Quote => write!(f, "'"),
+            Token::Quasiquote => write!(f, "`"),
+            Token::Unquote => write!(f, ","),
+            Token::UnquoteSplicing => write!(f, ",@"),
         }
     }
 }
 
 #[derive(Debug)]
-pub struct TokenError {
-    ch: char,
+pub enum TokenError {
+    Unexpected(char),
+    UnknownChar(String),
 }
 
 impl Error for TokenError {}
 
 impl fmt::Display for TokenError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "unexpected character {}", self.ch)
+        match self {
+            TokenError::Unexpected(ch) => write!(f, "unexpected character {}", ch),
+            TokenError::UnknownChar(name) => write!(f, "unknown character #\\{}", name),
+        }
+    }
+}
+
+/// Characters with a name, written `#\name`.
+const CHAR_NAMES: &[(&str, char)] = &[
+    ("space", ' '),
+    ("newline", '\n'),
+    ("tab", '\t'),
+    ("return", '\r'),
+    ("nul", '\0'),
+];
+
+/// The character written after `#\`: a name, `x` and a hex code, or the
+/// character itself.
+fn char_named(name: &str) -> Result<char, TokenError> {
+    let mut chars = name.chars();
+    if let (Some(c), None) = (chars.next(), chars.next()) {
+        return Ok(c);
+    }
+    if let Some((_, c)) = CHAR_NAMES.iter().find(|(n, _)| *n == name) {
+        return Ok(*c);
+    }
+    name.strip_prefix('x')
+        .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+        .and_then(char::from_u32)
+        .ok_or_else(|| TokenError::UnknownChar(name.to_string()))
+}
+
+/// How a character is written so that it reads back as the same character.
+pub fn char_literal(c: char) -> String {
+    match CHAR_NAMES.iter().find(|(_, ch)| *ch == c) {
+        Some((name, _)) => format!("#\\{}", name),
+        None if c.is_control() => format!("#\\x{:x}", c as u32),
+        None => format!("#\\{}", c),
     }
 }
 
@@ -40,33 +99,84 @@ pub fn tokenize(program: &str) -> Result<Vec<Token>, TokenError> {
     let re = Regex::new(
         r#"(?x)
     (?P<number> -? \d+ (\.\d+)?)
-    | (?P<symbol> [^\s()]+)
+    | (?P<string> " ( [^"\\] | \\. )* ")
+    | \#\\ (?P<char> [[:alnum:]]+ | . )
+    | : (?P<keyword> [^\s(){}\[\]'`,"]+)
+    | (?P<symbol> [^\s(){}\[\]'`,"]+)
     | (?P<lp>\()
     | (?P<rp>\))
+    | (?P<lb>\{)
+    | (?P<rb>\})
+    | (?P<lbr>\[)
+    | (?P<rbr>\])
+    | (?P<quote> ' | ` | ,@ | , )
+    | (?P<unterminated> ")
 "#,
     )
     .unwrap();
     let tokens: Vec<Token> = re
         .captures_iter(program)
-        .filter_map(|captures| {
+        .map(|captures| {
             if let Some(num) = captures.name("number") {
                 let num_str = num.as_str();
                 let n = num_str.parse::<f64>().unwrap();
-                Some(Ok(Token::Number(n)))
+                Ok(Token::Number(n))
+            } else if let Some(string) = captures.name("string") {
+                let s = string.as_str();
+                Ok(Token::String(unescape(&s[1..s.len() - 1])))
+            } else if let Some(name) = captures.name("char") {
+                char_named(name.as_str()).map(Token::Char)
+            } else if let Some(keyword) = captures.name("keyword") {
+                Ok(Token::Keyword(keyword.as_str().to_string()))
             } else if let Some(symbol) = captures.name("symbol") {
-                Some(Ok(Token::Symbol(symbol.as_str().to_string())))
+                Ok(Token::Symbol(symbol.as_str().to_string()))
             } else if captures.name("lp").is_some() {
-                Some(Ok(Token::LParen))
+                Ok(Token::LParen)
             } else if captures.name("rp").is_some() {
-                Some(Ok(Token::RParen))
+                Ok(Token::RParen)
+            } else if captures.name("lb").is_some() {
+                Ok(Token::LBrace)
+            } else if captures.name("rb").is_some() {
+                Ok(Token::RBrace)
+            } else if captures.name("lbr").is_some() {
+                Ok(Token::LBracket)
+            } else if captures.name("rbr").is_some() {
+                Ok(Token::RBracket)
+            } else if let Some(quote) = captures.name("quote") {
+                Ok(match quote.as_str() {
+                    "'" => Token::Quote,
+                    "`" => Token::Quasiquote,
+                    ",@" => Token::UnquoteSplicing,
+                    _ => Token::Unquote,
+                })
+            } else if captures.name("unterminated").is_some() {
+                Err(TokenError::Unexpected('"'))
             } else {
-                Some(Err(TokenError { ch: ' ' }))
+                Err(TokenError::Unexpected(' '))
             }
         })
         .collect::<Result<Vec<Token>, TokenError>>()?;
     Ok(tokens)
 }
 
+fn unescape(s: &str) -> String {
+    let mut result = String::new();
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            match chars.next() {
+                Some('n') => result.push('\n'),
+                Some('t') => result.push('\t'),
+                Some(c) => result.push(c),
+                None => {}
+            }
+        } else {
+            result.push(c);
+        }
+    }
+    result
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -135,4 +245,108 @@ mod tests {
             ]
         );
     }
+
+    #[test]
+    fn test_quotes() {
+        let tokens = tokenize("'a `(b ,c ,@d)").unwrap();
+        assert_eq!(
+            tokens,
+            vec![
+                Token::Quote,
+                Token::Symbol("a".to_string()),
+                Token::Quasiquote,
+                Token::LParen,
+                Token::Symbol("b".to_string()),
+                Token::Unquote,
+                Token::Symbol("c".to_string()),
+                Token::UnquoteSplicing,
+                Token::Symbol("d".to_string()),
+                Token::RParen
+            ]
+        );
+    }
+
+    #[test]
+    fn test_string() {
+        let tokens = tokenize(r#"(error "say \"hi\"\n" x)"#).unwrap();
+        assert_eq!(
+            tokens,
+            vec![
+                Token::LParen,
+                Token::Symbol("error".to_string()),
+                Token::String("say \"hi\"\n".to_string()),
+                Token::Symbol("x".to_string()),
+                Token::RParen
+            ]
+        );
+    }
+
+    #[test]
+    fn test_braces() {
+        let tokens = tokenize("{a 1}").unwrap();
+        assert_eq!(
+            tokens,
+            vec![
+                Token::LBrace,
+                Token::Symbol("a".to_string()),
+                Token::Number(1.0),
+                Token::RBrace
+            ]
+        );
+    }
+
+    #[test]
+    fn test_brackets() {
+        let tokens = tokenize("[a]").unwrap();
+        assert_eq!(
+            tokens,
+            vec![
+                Token::LBracket,
+                Token::Symbol("a".to_string()),
+                Token::RBracket
+            ]
+        );
+    }
+
+    #[test]
+    fn test_unterminated_string() {
+        assert!(tokenize(r#"(error "oops)"#).is_err());
+    }
+
+    #[test]
+    fn test_chars() {
+        let tokens = tokenize(r"(#\a #\space #\newline #\x41 #\) #\()").unwrap();
+        assert_eq!(
+            tokens,
+            vec![
+                Token::LParen,
+                Token::Char('a'),
+                Token::Char(' '),
+                Token::Char('\n'),
+                Token::Char('A'),
+                Token::Char(')'),
+                Token::Char('('),
+                Token::RParen
+            ]
+        );
+        assert!(tokenize(r"#\bogus").is_err());
+        assert_eq!(char_literal('\n'), r"#\newline");
+        assert_eq!(char_literal('\u{7}'), r"#\x7");
+    }
+
+    #[test]
+    fn test_keywords() {
+        let tokens = tokenize("(f :size 2 a:b)").unwrap();
+        assert_eq!(
+            tokens,
+            vec![
+                Token::LParen,
+                Token::Symbol("f".to_string()),
+                Token::Keyword("size".to_string()),
+                Token::Number(2.0),
+                Token::Symbol("a:b".to_string()),
+                Token::RParen
+            ]
+        );
+    }
 }
@@ -0,0 +1,108 @@
+use std::{fmt, ops::Deref, rc::Rc};
+
+use crate::value::Value;
+
+/// The items of a `Value::List`. Cloning a list, or taking the rest of it with
+/// `cdr`, shares the items instead of copying them.
+#[derive(Clone)]
+pub struct List {
+    items: Rc<[Value]>,
+    start: usize,
+}
+
+impl List {
+    pub fn new() -> Self {
+        List::default()
+    }
+
+    pub fn as_slice(&self) -> &[Value] {
+        &self.items[self.start..]
+    }
+
+    /// The list without its first `n` items.
+    pub fn tail(&self, n: usize) -> List {
+        List {
+            items: self.items.clone(),
+            start: (self.start + n).min(self.items.len()),
+        }
+    }
+}
+
+impl Default for List {
+    fn default() -> Self {
+        List {
+            items: Rc::new([]),
+            start: 0,
+        }
+    }
+}
+
+impl Deref for List {
+    type Target = [Value];
+
+    fn deref(&self) -> &[Value] {
+        self.as_slice()
+    }
+}
+
+impl From<Vec<Value>> for List {
+    fn from(items: Vec<Value>) -> Self {
+        List {
+            items: items.into(),
+            start: 0,
+        }
+    }
+}
+
+impl From<&[Value]> for List {
+    fn from(items: &[Value]) -> Self {
+        List {
+            items: items.into(),
+            start: 0,
+        }
+    }
+}
+
+impl FromIterator<Value> for List {
+    fn from_iter<I: IntoIterator<Item = Value>>(iter: I) -> Self {
+        List {
+            items: iter.into_iter().collect(),
+            start: 0,
+        }
+    }
+}
+
+impl<'a> IntoIterator for &'a List {
+    type Item = &'a Value;
+    type IntoIter = std::slice::Iter<'a, Value>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.as_slice().iter()
+    }
+}
+
+impl PartialEq for List {
+    fn eq(&self, other: &Self) -> bool {
+        self.as_slice() == other.as_slice()
+    }
+}
+
+impl fmt::Debug for List {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_list().entries(self.as_slice()).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tail_shares_items() {
+        let list = List::from(vec![Value::Number(1.0), Value::Number(2.0)]);
+        let rest = list.tail(1);
+        assert_eq!(rest.as_slice(), &[Value::Number(2.0)]);
+        assert!(Rc::ptr_eq(&list.items, &rest.items));
+        assert!(list.tail(5).is_empty());
+    }
+}
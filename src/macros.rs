@@ -0,0 +1,363 @@
+use std::rc::Rc;
+
+use crate::{
+    environment::Env,
+    error::Exception,
+    map::{Key, Map},
+    pair,
+    program::apply,
+    symbol::{known, Symbol},
+    value::Value,
+    vector::Vector,
+};
+
+/// Expands every macro call in `node`, including calls produced by other
+/// expansions, and the items of vector and map literals. Quoted data,
+/// parameter lists and `case` datums are left alone.
+pub fn expand(node: &Value, env: &Env) -> Result<Value, Exception> {
+    let list = match node {
+        Value::List(l) if !l.is_empty() => l,
+        Value::Vector(v) => {
+            let items = v
+                .iter()
+                .map(|item| expand(item, env))
+                .collect::<Result<Vector<_>, _>>()?;
+            return Ok(Value::Vector(items));
+        }
+        Value::Map(m) => {
+            let mut map = Map::new();
+            for (key, val) in m.iter() {
+                let key = expand(key, env)?;
+                let key = Key::new(&key).ok_or_else(|| {
+                    Exception::new("type-error", format!("Invalid map key {}", key))
+                })?;
+                map = map.insert(key, expand(val, env)?);
+            }
+            return Ok(Value::Map(Rc::new(map)));
+        }
+        _ => return Ok(node.clone()),
+    };
+    if let Some(expanded) = expand_1(node, env)? {
+        return expand(&expanded, env);
+    }
+    let head = match &list[0] {
+        Value::Symbol(s) => Some(*s),
+        _ => None,
+    };
+    let expanded = match head {
+        Some(
+            known::QUOTE | known::DEFINE_SYNTAX | known::DEFSTRUCT | known::DEFINE_RECORD_TYPE,
+        ) => return Ok(node.clone()),
+        Some(known::QUASIQUOTE) if list.len() == 2 => {
+            vec![list[0].clone(), expand_quasiquote(&list[1], 1, env)?]
+        }
+        Some(known::FN) => keep(list, 2, env)?,
+        Some(known::DEFMACRO) => keep(list, 3, env)?,
+        Some(known::SHIFT) => keep(list, 2, env)?,
+        Some(known::LET | known::LET_STAR | known::LETREC | known::LET_VALUES) => {
+            expand_let(list, env)?
+        }
+        Some(known::RECEIVE) => keep(list, 2, env)?,
+        Some(known::HANDLER_BIND) => {
+            let mut expanded = vec![list[0].clone()];
+            match list.get(1) {
+                Some(Value::List(clauses)) => {
+                    expanded.push(Value::List(keep_each(clauses, 1, env)?.into()))
+                }
+                Some(other) => expanded.push(other.clone()),
+                None => {}
+            }
+            expanded.extend(expand_all(list.get(2..).unwrap_or(&[]), env)?);
+            expanded
+        }
+        Some(known::RESTART_CASE) => {
+            let mut expanded = keep(&list[..list.len().min(2)], 1, env)?;
+            expanded.extend(keep_each(list.get(2..).unwrap_or(&[]), 2, env)?);
+            expanded
+        }
+        Some(known::COND) => {
+            let mut expanded = vec![list[0].clone()];
+            for clause in &list[1..] {
+                expanded.push(match clause {
+                    Value::List(c) => Value::List(expand_all(c, env)?.into()),
+                    _ => clause.clone(),
+                });
+            }
+            expanded
+        }
+        Some(known::CASE | known::MATCH) => {
+            let mut expanded = keep(&list[..list.len().min(2)], 1, env)?;
+            for clause in list.iter().skip(2) {
+                expanded.push(match clause {
+                    Value::List(c) if !c.is_empty() => Value::List(keep(c, 1, env)?.into()),
+                    _ => clause.clone(),
+                });
+            }
+            expanded
+        }
+        _ => expand_all(list, env)?,
+    };
+    Ok(Value::List(expanded.into()))
+}
+
+/// Expands `node` once if it is a call to a macro.
+pub fn expand_1(node: &Value, env: &Env) -> Result<Option<Value>, Exception> {
+    if let Value::List(l) = node {
+        if let Some(Value::Symbol(s)) = l.first() {
+            let found = env.borrow().get(*s);
+            match found {
+                Some(Value::Macro(expander)) => {
+                    return apply(&expander, &l[1..]).map(|code| Some(pair::to_code(&code)))
+                }
+                Some(Value::SyntaxRules(rules)) => return rules.expand(node).map(Some),
+                _ => {}
+            }
+        }
+    }
+    Ok(None)
+}
+
+fn expand_all(nodes: &[Value], env: &Env) -> Result<Vec<Value>, Exception> {
+    nodes.iter().map(|node| expand(node, env)).collect()
+}
+
+/// Keeps the first `n` elements as they are and expands the rest.
+fn keep(list: &[Value], n: usize, env: &Env) -> Result<Vec<Value>, Exception> {
+    let n = n.min(list.len());
+    let mut expanded = list[..n].to_vec();
+    expanded.extend(expand_all(&list[n..], env)?);
+    Ok(expanded)
+}
+
+/// Keeps the first `n` elements of every list in `clauses`.
+fn keep_each(clauses: &[Value], n: usize, env: &Env) -> Result<Vec<Value>, Exception> {
+    clauses
+        .iter()
+        .map(|clause| match clause {
+            Value::List(c) => keep(c, n, env).map(|l| Value::List(l.into())),
+            _ => Ok(clause.clone()),
+        })
+        .collect()
+}
+
+/// Only the init expressions and the body of a `let` form are code.
+fn expand_let(list: &[Value], env: &Env) -> Result<Vec<Value>, Exception> {
+    let at = match list.get(1) {
+        Some(Value::Symbol(_)) => 2,
+        _ => 1,
+    };
+    let mut expanded = list[..at.min(list.len())].to_vec();
+    if let Some(bindings) = list.get(at) {
+        expanded.push(match bindings {
+            Value::List(bs) => Value::List(
+                bs.iter()
+                    .map(|binding| match binding {
+                        Value::List(b) => keep(b, 1, env).map(|l| Value::List(l.into())),
+                        _ => Ok(binding.clone()),
+                    })
+                    .collect::<Result<Vec<Value>, Exception>>()?
+                    .into(),
+            ),
+            _ => bindings.clone(),
+        });
+        expanded.extend(expand_all(&list[at + 1..], env)?);
+    }
+    // A named let often calls its function `loop`. Renaming it in the body
+    // keeps its calls from being taken for the `loop` form.
+    if let [_, Value::Symbol(known::LOOP), ..] = expanded.as_slice() {
+        let name = Symbol::gensym("loop");
+        expanded[1] = Value::Symbol(name);
+        for node in &mut expanded[3..] {
+            *node = rename(node, known::LOOP, name, 0);
+        }
+    }
+    Ok(expanded)
+}
+
+/// Replaces `from` with `to` in code, but not in quoted data.
+fn rename(node: &Value, from: Symbol, to: Symbol, quasi: usize) -> Value {
+    match node {
+        Value::Symbol(s) if *s == from && quasi == 0 => Value::Symbol(to),
+        Value::List(l) => match l.as_slice() {
+            [Value::Symbol(known::QUOTE), _] => node.clone(),
+            [Value::Symbol(known::QUASIQUOTE), x] => {
+                Value::List(vec![l[0].clone(), rename(x, from, to, quasi + 1)].into())
+            }
+            [Value::Symbol(known::UNQUOTE | known::UNQUOTE_SPLICING), x] if quasi > 0 => {
+                Value::List(vec![l[0].clone(), rename(x, from, to, quasi - 1)].into())
+            }
+            _ => Value::List(l.iter().map(|item| rename(item, from, to, quasi)).collect()),
+        },
+        Value::Vector(v) => {
+            Value::Vector(v.iter().map(|item| rename(item, from, to, quasi)).collect())
+        }
+        _ => node.clone(),
+    }
+}
+
+/// Only the unquoted parts of a quasiquote template are code.
+fn expand_quasiquote(node: &Value, depth: usize, env: &Env) -> Result<Value, Exception> {
+    let list = match node {
+        Value::List(l) => l,
+        _ => return Ok(node.clone()),
+    };
+    match list.as_slice() {
+        [Value::Symbol(known::UNQUOTE | known::UNQUOTE_SPLICING), x] => {
+            let x = if depth == 1 {
+                expand(x, env)?
+            } else {
+                expand_quasiquote(x, depth - 1, env)?
+            };
+            Ok(Value::List(vec![list[0].clone(), x].into()))
+        }
+        [Value::Symbol(known::QUASIQUOTE), x] => Ok(Value::List(
+            vec![list[0].clone(), expand_quasiquote(x, depth + 1, env)?].into(),
+        )),
+        _ => list
+            .iter()
+            .map(|node| expand_quasiquote(node, depth, env))
+            .collect::<Result<Vec<Value>, Exception>>()
+            .map(|l| Value::List(l.into())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, rc::Rc};
+
+    use crate::{environment::Environment, program::evaluate, symbol::Symbol};
+
+    use super::*;
+
+    fn eval(source: &str) -> Result<Value, Exception> {
+        let mut env = Rc::new(RefCell::new(Environment::new()));
+        evaluate(source, &mut env)
+    }
+
+    fn symbols(names: &[&str]) -> Value {
+        Value::List(
+            names
+                .iter()
+                .map(|s| Value::Symbol(Symbol::new(s)))
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn test_defmacro() {
+        let source = "(defmacro my-unless (test &rest body)
+                        `(if ,test 0 (and ,@body)))
+                      (my-unless (gt 1 2) 1 2)
+                      (my-unless (gt 2 1) 1 2)";
+        let res = eval(source).unwrap();
+        assert_eq!(
+            res,
+            Value::List(vec![Value::Number(2.0), Value::Number(0.0)].into())
+        );
+    }
+
+    #[test]
+    fn test_macro_arguments_are_not_evaluated() {
+        let source = "(defmacro name-of (x) `(quote ,x))
+                      (name-of undefined)";
+        let res = eval(source).unwrap();
+        assert_eq!(
+            res,
+            Value::List(vec![Value::Symbol(Symbol::new("undefined"))].into())
+        );
+    }
+
+    #[test]
+    fn test_macro_expanding_to_macro() {
+        let source = "(defmacro my-when (test &rest body) `(if ,test (and ,@body)))
+                      (defmacro my-unless (test &rest body) `(my-when (not ,test) ,@body))
+                      (define f (fn (x) (my-unless (gt x 2) (* x 10))))
+                      (list (f 1) (f 3))";
+        let res = eval(source).unwrap();
+        assert_eq!(
+            res,
+            Value::List(vec![Value::List(vec![Value::Number(10.0), Value::Nil].into())].into())
+        );
+    }
+
+    #[test]
+    fn test_quoted_data_is_not_expanded() {
+        let source = "(defmacro m () 1)
+                      '(m)";
+        let res = eval(source).unwrap();
+        assert_eq!(res, Value::List(vec![symbols(&["m"])].into()));
+    }
+
+    #[test]
+    fn test_macroexpand() {
+        let source = "(defmacro my-when (test &rest body) `(if ,test (and ,@body)))
+                      (defmacro my-unless (test &rest body) `(my-when (not ,test) ,@body))
+                      (macroexpand-1 '(my-unless x y))
+                      (macroexpand '(my-unless x y))";
+        let res = eval(source).unwrap();
+        assert_eq!(
+            res,
+            Value::List(
+                vec![
+                    Value::List(
+                        vec![
+                            Value::Symbol(Symbol::new("my-when")),
+                            symbols(&["not", "x"]),
+                            Value::Symbol(Symbol::new("y")),
+                        ]
+                        .into()
+                    ),
+                    Value::List(
+                        vec![
+                            Value::Symbol(Symbol::new("if")),
+                            symbols(&["not", "x"]),
+                            symbols(&["and", "y"]),
+                        ]
+                        .into()
+                    ),
+                ]
+                .into()
+            )
+        );
+    }
+
+    #[test]
+    fn test_gensym() {
+        let source = "(defmacro swap! (a b)
+                        (let ((tmp (gensym)))
+                          `(let ((,tmp ,a)) (set! ,a ,b) (set! ,b ,tmp))))
+                      (define tmp 1)
+                      (define other 2)
+                      (swap! tmp other)
+                      (list tmp other)";
+        let res = eval(source).unwrap();
+        assert_eq!(
+            res,
+            Value::List(
+                vec![Value::List(
+                    vec![Value::Number(2.0), Value::Number(1.0)].into()
+                )]
+                .into()
+            )
+        );
+    }
+
+    #[test]
+    fn test_gensym_is_unique() {
+        assert_ne!(eval("(gensym)").unwrap(), eval("(gensym)").unwrap());
+    }
+
+    #[test]
+    fn test_macros_in_vector_literals() {
+        let source = "(defmacro my-when (test &rest body) `(if ,test (and ,@body)))
+                      [(my-when 1 2) 3 [(my-when 1 4)]]";
+        assert_eq!(eval(source).unwrap().to_string(), "([2 3 [4]])");
+    }
+
+    #[test]
+    fn test_macros_in_map_literals() {
+        let source = "(defmacro my-when (test &rest body) `(if ,test (and ,@body)))
+                      {:a (my-when 1 2) (my-when 1 :b) {:c (my-when 1 3)}}";
+        assert_eq!(eval(source).unwrap().to_string(), "({:a 2 :b {:c 3}})");
+    }
+}
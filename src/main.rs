@@ -1,28 +1,55 @@
+mod builtins;
+mod conditions;
 mod environment;
+mod error;
+mod generator;
+mod hamt;
+mod keyword;
 mod lexer;
+mod list;
+mod macros;
+mod map;
+mod pair;
 mod parser;
+mod pattern;
 mod program;
+mod promise;
+mod record;
+mod stack;
+mod symbol;
+mod syntax_rules;
 mod value;
+mod vector;
 
 use std::{cell::RefCell, rc::Rc};
 
-use linefeed::{Interface, ReadResult};
+use linefeed::{DefaultTerminal, Interface, ReadResult};
 use value::Value;
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let reader = Interface::new("λ ")?;
+    let reader = Rc::new(Interface::new("λ ")?);
     let mut env = Rc::new(RefCell::new(environment::Environment::new()));
-    reader.set_prompt(format!("{}", "λ ").as_ref())?;
+    reader.set_prompt("λ ")?;
+    let debugger_reader = reader.clone();
+    conditions::set_debugger(Rc::new(move |condition, restarts| {
+        choose_restart(&debugger_reader, condition, restarts)
+    }));
     while let ReadResult::Input(input) = reader.read_line()? {
         if input.eq("quit") {
             break;
         }
-        let val = program::evaluate(input.as_ref(), &mut env)?;
+        let val = match program::evaluate(input.as_ref(), &mut env) {
+            Ok(val) => val,
+            Err(e) => {
+                println!("{e}");
+                continue;
+            }
+        };
         match val {
             Value::Nil => println!("nil"),
             Value::Number(n) => println!("{n}"),
             Value::Symbol(s) => println!("{s}"),
-            Value::Lambda(args, body) => {
+            Value::Lambda(args, body, _) => {
                 println!("fn(");
                 for arg in args {
                     println!("{} ", arg);
@@ -37,3 +64,32 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     }
     Ok(())
 }
+
+/// Offers the active restarts for an unhandled error. The user enters the number
+/// of a restart followed by its arguments, which are read but not evaluated.
+fn choose_restart(
+    reader: &Interface<DefaultTerminal>,
+    condition: &Value,
+    restarts: &[symbol::Symbol],
+) -> Option<(usize, Vec<Value>)> {
+    println!("Unhandled {condition}");
+    println!("Restarts:");
+    for (i, name) in restarts.iter().enumerate() {
+        println!("  {i}: {name}");
+    }
+    println!("  {}: abort", restarts.len());
+    reader.set_prompt("restart> ").ok()?;
+    let input = reader.read_line();
+    reader.set_prompt("λ ").ok()?;
+    let input = match input {
+        Ok(ReadResult::Input(input)) => input,
+        _ => return None,
+    };
+    let mut values = parser::parse_program(&input).ok()?.into_iter();
+    match values.next() {
+        Some(Value::Number(n)) if n >= 0.0 && n.fract() == 0.0 => {
+            Some((n as usize, values.collect()))
+        }
+        _ => None,
+    }
+}
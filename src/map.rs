@@ -0,0 +1,205 @@
+use std::{
+    fmt,
+    hash::{Hash, Hasher},
+};
+
+use crate::{hamt::Hamt, value::Value, vector::Vector};
+
+/// A value that can be used as a map key: a number, symbol, keyword, string,
+/// character, `nil` or a list of keys.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Key(Value);
+
+impl Key {
+    pub fn new(v: &Value) -> Option<Key> {
+        hashable(v).then(|| Key(v.clone()))
+    }
+
+    pub fn value(&self) -> &Value {
+        &self.0
+    }
+}
+
+fn hashable(v: &Value) -> bool {
+    match v {
+        Value::Number(n) => !n.is_nan(),
+        Value::Symbol(_) | Value::Keyword(_) | Value::String(_) | Value::Char(_) | Value::Nil => {
+            true
+        }
+        Value::List(l) => l.iter().all(hashable),
+        _ => false,
+    }
+}
+
+impl Eq for Key {}
+
+impl Hash for Key {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        hash_value(&self.0, state)
+    }
+}
+
+fn hash_value<H: Hasher>(v: &Value, state: &mut H) {
+    std::mem::discriminant(v).hash(state);
+    match v {
+        // 0 and -0 are equal, so they must hash the same.
+        Value::Number(n) => (n + 0.0).to_bits().hash(state),
+        Value::Symbol(s) => s.hash(state),
+        Value::String(s) => s.hash(state),
+        Value::Char(c) => c.hash(state),
+        Value::Keyword(k) => k.hash(state),
+        Value::List(l) => {
+            l.len().hash(state);
+            for v in l {
+                hash_value(v, state);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// A persistent hash map that remembers the order its keys were first
+/// inserted in. The entries are kept in that order in a vector, where removed
+/// ones leave a gap until there are enough gaps to compact it, and a trie maps
+/// each key to its position.
+#[derive(Clone, Default)]
+pub struct Map {
+    entries: Vector<Option<(Key, Value)>>,
+    index: Hamt<Key, usize>,
+}
+
+impl Map {
+    pub fn new() -> Self {
+        Map::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.index.len()
+    }
+
+    pub fn get(&self, key: &Key) -> Option<&Value> {
+        let i = self.index.get(key)?;
+        self.entries.get(*i)?.as_ref().map(|(_, val)| val)
+    }
+
+    /// Returns a map with the value of `key` replaced, keeping its position, or
+    /// with `key` added at the end.
+    #[must_use]
+    pub fn insert(&self, key: Key, val: Value) -> Map {
+        match self.index.get(&key) {
+            Some(&i) => Map {
+                entries: self
+                    .entries
+                    .set(i, Some((key, val)))
+                    .expect("the index is in bounds"),
+                index: self.index.clone(),
+            },
+            None => Map {
+                index: self.index.insert(key.clone(), self.entries.len()),
+                entries: self.entries.push(Some((key, val))),
+            },
+        }
+    }
+
+    #[must_use]
+    pub fn remove(&self, key: &Key) -> Map {
+        let i = match self.index.get(key) {
+            Some(&i) => i,
+            None => return self.clone(),
+        };
+        let map = Map {
+            entries: self.entries.set(i, None).expect("the index is in bounds"),
+            index: self.index.remove(key),
+        };
+        if map.entries.len() > 2 * map.len() + 32 {
+            map.iter()
+                .map(|(key, val)| (Key(key.clone()), val.clone()))
+                .collect()
+        } else {
+            map
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&Value, &Value)> {
+        self.entries
+            .iter()
+            .flatten()
+            .map(|(key, val)| (key.value(), val))
+    }
+}
+
+impl fmt::Debug for Map {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+impl FromIterator<(Key, Value)> for Map {
+    fn from_iter<I: IntoIterator<Item = (Key, Value)>>(iter: I) -> Self {
+        iter.into_iter()
+            .fold(Map::new(), |map, (key, val)| map.insert(key, val))
+    }
+}
+
+/// Maps are equal when they have the same entries, in any order.
+impl PartialEq for Map {
+    fn eq(&self, other: &Self) -> bool {
+        self.len() == other.len()
+            && self
+                .entries
+                .iter()
+                .flatten()
+                .all(|(key, val)| other.get(key) == Some(val))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::symbol::Symbol;
+
+    fn key(s: &str) -> Key {
+        Key::new(&Value::Symbol(Symbol::new(s))).unwrap()
+    }
+
+    #[test]
+    fn test_insertion_order() {
+        let map = Map::new()
+            .insert(key("b"), Value::Number(1.0))
+            .insert(key("a"), Value::Number(2.0))
+            .insert(key("c"), Value::Number(3.0))
+            .insert(key("b"), Value::Number(4.0));
+        let removed = map.remove(&key("a"));
+        let keys = removed
+            .iter()
+            .map(|(k, _)| k.to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(keys, vec!["b", "c"]);
+        assert_eq!(removed.get(&key("c")), Some(&Value::Number(3.0)));
+        assert_eq!(removed.get(&key("a")), None);
+        assert_eq!(map.get(&key("a")), Some(&Value::Number(2.0)));
+        assert_eq!(map.get(&key("b")), Some(&Value::Number(4.0)));
+    }
+
+    #[test]
+    fn test_remove_compacts() {
+        let map = (0..100)
+            .map(|i| (Key::new(&Value::Number(i as f64)).unwrap(), Value::Nil))
+            .collect::<Map>();
+        let map = (0..90).fold(map, |map, i| {
+            map.remove(&Key::new(&Value::Number(i as f64)).unwrap())
+        });
+        assert_eq!(map.len(), 10);
+        assert!(map.entries.len() < 100);
+        assert_eq!(map.iter().next().unwrap().0, &Value::Number(90.0));
+    }
+
+    #[test]
+    fn test_keys() {
+        let zero = Key::new(&Value::Number(0.0)).unwrap();
+        let map = Map::new().insert(zero, Value::Nil);
+        assert!(map.get(&Key::new(&Value::Number(-0.0)).unwrap()).is_some());
+        assert!(Key::new(&Value::Number(f64::NAN)).is_none());
+        assert!(Key::new(&Value::List(vec![Value::String("x".to_string())].into())).is_some());
+    }
+}
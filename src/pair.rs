@@ -0,0 +1,208 @@
+use std::{cell::RefCell, fmt, rc::Rc};
+
+use crate::{list::List, value::Value};
+
+/// A cons cell made by `cons`. Its `cdr` can be anything, so pairs can build
+/// improper lists, and `set-car!` and `set-cdr!` change it in place.
+pub struct Pair {
+    pub car: RefCell<Value>,
+    pub cdr: RefCell<Value>,
+}
+
+impl Pair {
+    pub fn new(car: Value, cdr: Value) -> Self {
+        Pair {
+            car: RefCell::new(car),
+            cdr: RefCell::new(cdr),
+        }
+    }
+}
+
+/// Splits a non-empty list, made of pairs or not, into its first item and the
+/// rest.
+pub fn uncons(v: &Value) -> Option<(Value, Value)> {
+    match v {
+        Value::Pair(p) => Some((p.car.borrow().clone(), p.cdr.borrow().clone())),
+        Value::List(l) if !l.is_empty() => Some((l[0].clone(), Value::List(l.tail(1)))),
+        _ => None,
+    }
+}
+
+/// Walks the items of a list, made of pairs or not, without copying them. What
+/// is left when it stops is in `rest`, an empty list if the list was proper.
+pub struct Items {
+    pub rest: Value,
+}
+
+impl Iterator for Items {
+    type Item = Value;
+
+    fn next(&mut self) -> Option<Value> {
+        let (item, rest) = uncons(&self.rest)?;
+        self.rest = rest;
+        Some(item)
+    }
+}
+
+pub fn items(v: &Value) -> Items {
+    Items { rest: v.clone() }
+}
+
+/// Whether `v` is a list that ends in an empty list rather than in some other
+/// `cdr`.
+pub fn is_proper(v: &Value) -> bool {
+    let mut walk = items(v);
+    for _ in walk.by_ref() {}
+    matches!(walk.rest, Value::List(_))
+}
+
+/// The items of a proper list, whether it is made of pairs or not, or `None` if
+/// `v` is not one.
+pub fn proper_list(v: &Value) -> Option<List> {
+    let mut items = vec![];
+    let mut rest = v.clone();
+    loop {
+        match rest {
+            Value::List(l) if items.is_empty() => return Some(l),
+            Value::List(l) => {
+                items.extend(l.iter().cloned());
+                return Some(items.into());
+            }
+            Value::Pair(p) => {
+                items.push(p.car.borrow().clone());
+                rest = p.cdr.borrow().clone();
+            }
+            _ => return None,
+        }
+    }
+}
+
+/// Turns the proper lists made of pairs in code built by a macro into lists,
+/// which is what the evaluator and the expander take apart.
+pub fn to_code(v: &Value) -> Value {
+    match v {
+        Value::List(l) if l.iter().any(has_pairs) => Value::List(l.iter().map(to_code).collect()),
+        Value::Pair(_) => match proper_list(v) {
+            Some(l) => Value::List(l.iter().map(to_code).collect()),
+            None => v.clone(),
+        },
+        _ => v.clone(),
+    }
+}
+
+fn has_pairs(v: &Value) -> bool {
+    match v {
+        Value::Pair(_) => true,
+        Value::List(l) => l.iter().any(has_pairs),
+        _ => false,
+    }
+}
+
+/// Compares two lists item by item, whether they are made of pairs or not.
+pub fn lists_eq(a: &Value, b: &Value) -> bool {
+    let (mut a, mut b) = (a.clone(), b.clone());
+    loop {
+        match (uncons(&a), uncons(&b)) {
+            (Some((x, rest_a)), Some((y, rest_b))) => {
+                if x != y {
+                    return false;
+                }
+                (a, b) = (rest_a, rest_b);
+            }
+            (None, None) => {
+                return match (&a, &b) {
+                    (Value::List(_), Value::List(_)) => true,
+                    (Value::List(_), _) | (_, Value::List(_)) => false,
+                    _ => a == b,
+                }
+            }
+            _ => return false,
+        }
+    }
+}
+
+/// Prints the items after the first one of a list that starts with a pair, and
+/// a dot before the last `cdr` if it is not a list.
+pub fn write_rest(f: &mut fmt::Formatter, mut rest: Value) -> fmt::Result {
+    loop {
+        match rest {
+            Value::Pair(p) => {
+                write!(f, " {}", p.car.borrow())?;
+                let next = p.cdr.borrow().clone();
+                rest = next;
+            }
+            Value::List(l) => {
+                for item in l.iter() {
+                    write!(f, " {}", item)?;
+                }
+                return Ok(());
+            }
+            other => return write!(f, " . {}", other),
+        }
+    }
+}
+
+// Dropping the rest of the list one pair at a time instead of recursively, long
+// lists would overflow the native stack otherwise.
+impl Drop for Pair {
+    fn drop(&mut self) {
+        let mut next = self.cdr.replace(Value::Nil);
+        while let Value::Pair(p) = next {
+            next = match Rc::try_unwrap(p) {
+                Ok(pair) => pair.cdr.replace(Value::Nil),
+                Err(_) => break,
+            };
+        }
+    }
+}
+
+impl fmt::Debug for Pair {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Pair({:?}, {:?})", self.car.borrow(), self.cdr.borrow())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cons(car: Value, cdr: Value) -> Value {
+        Value::Pair(Rc::new(Pair::new(car, cdr)))
+    }
+
+    #[test]
+    fn test_proper_list() {
+        let tail = Value::List(vec![Value::Number(2.0)].into());
+        let list = cons(Value::Number(1.0), tail);
+        assert_eq!(
+            proper_list(&list).unwrap().as_slice(),
+            &[Value::Number(1.0), Value::Number(2.0)]
+        );
+        assert!(proper_list(&cons(Value::Number(1.0), Value::Number(2.0))).is_none());
+    }
+
+    #[test]
+    fn test_items() {
+        let list = cons(
+            Value::Number(1.0),
+            Value::List(vec![Value::Number(2.0)].into()),
+        );
+        assert!(items(&list).eq([Value::Number(1.0), Value::Number(2.0)]));
+        assert!(is_proper(&list));
+        let improper = cons(Value::Number(1.0), Value::Number(2.0));
+        let mut walk = items(&improper);
+        assert_eq!(walk.next(), Some(Value::Number(1.0)));
+        assert_eq!(walk.next(), None);
+        assert_eq!(walk.rest, Value::Number(2.0));
+        assert!(!is_proper(&improper));
+    }
+
+    #[test]
+    fn test_long_list() {
+        let mut list = Value::List(List::new());
+        for i in 0..1_000_000 {
+            list = cons(Value::Number(i as f64), list);
+        }
+        assert_eq!(proper_list(&list).unwrap().len(), 1_000_000);
+    }
+}
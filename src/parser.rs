@@ -1,8 +1,14 @@
 use std::error::Error;
 use std::fmt;
 
+use std::rc::Rc;
+
 use crate::{
+    keyword::Keyword,
     lexer::{tokenize, Token},
+    map::{Key, Map},
+    pair::Pair,
+    symbol::Symbol,
     value::Value,
 };
 
@@ -19,33 +25,34 @@ impl fmt::Display for ParseError {
 
 impl Error for ParseError {}
 
-pub fn parse(program: &str) -> Result<Value, ParseError> {
-    let tok_res = tokenize(program).unwrap();
+pub fn parse_program(program: &str) -> Result<Vec<Value>, ParseError> {
+    let tok_res = tokenize(program).map_err(|e| ParseError { err: e.to_string() })?;
     let mut tokens = tok_res.into_iter().rev().collect::<Vec<_>>();
     let mut expressions: Vec<Value> = Vec::new();
     while !tokens.is_empty() {
         let parsed_expression = parse_expression(&mut tokens)?;
         expressions.push(parsed_expression);
     }
-
-    if expressions.len() == 1 {
-        Ok(expressions.pop().unwrap())
-    } else {
-        Ok(Value::List(expressions))
-    }
+    Ok(expressions)
 }
 
 fn parse_expression(tokens: &mut Vec<Token>) -> Result<Value, ParseError> {
     let token = tokens.pop();
     match token {
         Some(Token::Number(n)) => Ok(Value::Number(n)),
-        Some(Token::Symbol(s)) => Ok(Value::Symbol(s)),
+        Some(Token::Symbol(s)) => Ok(Value::Symbol(Symbol::new(&s))),
+        Some(Token::String(s)) => Ok(Value::String(s)),
+        Some(Token::Char(c)) => Ok(Value::Char(c)),
+        Some(Token::Keyword(name)) => Ok(Value::Keyword(Keyword::new(&name))),
         Some(Token::LParen) => {
             let mut list: Vec<Value> = Vec::new();
             while !tokens.is_empty() {
                 if tokens.last() == Some(&Token::RParen) {
                     tokens.pop();
-                    return Ok(Value::List(list));
+                    return Ok(Value::List(list.into()));
+                } else if tokens.last() == Some(&Token::Symbol(".".to_string())) {
+                    tokens.pop();
+                    return dotted(list, tokens);
                 } else {
                     let parsed_expression = parse_expression(tokens)?;
                     list.push(parsed_expression);
@@ -58,22 +65,122 @@ fn parse_expression(tokens: &mut Vec<Token>) -> Result<Value, ParseError> {
         Some(Token::RParen) => Err(ParseError {
             err: "Unexpected closing parenthesis".to_string(),
         }),
+        Some(Token::LBrace) => map_literal(tokens),
+        Some(Token::LBracket) => vector_literal(tokens),
+        Some(Token::RBracket) => Err(ParseError {
+            err: "Unexpected closing bracket".to_string(),
+        }),
+        Some(Token::RBrace) => Err(ParseError {
+            err: "Unexpected closing brace".to_string(),
+        }),
+        Some(Token::Quote) => quoted("quote", tokens),
+        Some(Token::Quasiquote) => quoted("quasiquote", tokens),
+        Some(Token::Unquote) => quoted("unquote", tokens),
+        Some(Token::UnquoteSplicing) => quoted("unquote-splicing", tokens),
         None => Err(ParseError {
             err: "Unexpected end of input".to_string(),
         }),
     }
 }
 
+/// `{k v ...}` is read as a map of the key and value expressions, which are
+/// evaluated when the map is.
+fn map_literal(tokens: &mut Vec<Token>) -> Result<Value, ParseError> {
+    let mut map = Map::new();
+    while let Some(token) = tokens.last() {
+        if token == &Token::RBrace {
+            tokens.pop();
+            return Ok(Value::Map(Rc::new(map)));
+        }
+        let key = parse_expression(tokens)?;
+        if tokens.last() == Some(&Token::RBrace) {
+            return Err(ParseError {
+                err: format!("Missing value for key {} in map", key),
+            });
+        }
+        let val = parse_expression(tokens)?;
+        let key = Key::new(&key).ok_or_else(|| ParseError {
+            err: format!("Invalid map key {}", key),
+        })?;
+        if map.get(&key).is_some() {
+            return Err(ParseError {
+                err: format!("Duplicate key {} in map", key.value()),
+            });
+        }
+        map = map.insert(key, val);
+    }
+    Err(ParseError {
+        err: "Unbalanced braces".to_string(),
+    })
+}
+
+/// The rest of a dotted list like `(a b . c)`, after the dot. A proper list
+/// after the dot just continues the list, anything else ends it in pairs.
+fn dotted(list: Vec<Value>, tokens: &mut Vec<Token>) -> Result<Value, ParseError> {
+    if list.is_empty() || tokens.last() == Some(&Token::RParen) {
+        return Err(ParseError {
+            err: "Expected an expression on both sides of a dot".to_string(),
+        });
+    }
+    let tail = parse_expression(tokens)?;
+    if tokens.pop() != Some(Token::RParen) {
+        return Err(ParseError {
+            err: "Expected a closing parenthesis after the expression after a dot".to_string(),
+        });
+    }
+    Ok(match tail {
+        Value::List(rest) => Value::List(list.into_iter().chain(rest.iter().cloned()).collect()),
+        tail => list
+            .into_iter()
+            .rev()
+            .fold(tail, |cdr, car| Value::Pair(Rc::new(Pair::new(car, cdr)))),
+    })
+}
+
+/// `[x ...]` is read as a vector of the expressions, which are evaluated when
+/// the vector is.
+fn vector_literal(tokens: &mut Vec<Token>) -> Result<Value, ParseError> {
+    let mut items = vec![];
+    while let Some(token) = tokens.last() {
+        if token == &Token::RBracket {
+            tokens.pop();
+            return Ok(Value::Vector(items.into()));
+        }
+        items.push(parse_expression(tokens)?);
+    }
+    Err(ParseError {
+        err: "Unbalanced brackets".to_string(),
+    })
+}
+
+/// `'x` is read as `(quote x)`, and likewise for the other quote characters.
+fn quoted(name: &str, tokens: &mut Vec<Token>) -> Result<Value, ParseError> {
+    let expression = parse_expression(tokens)?;
+    Ok(Value::List(
+        vec![Value::Symbol(Symbol::new(name)), expression].into(),
+    ))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    /// Parses a single expression, or a list of them if there are several.
+    fn parse(program: &str) -> Result<Value, ParseError> {
+        let mut expressions = parse_program(program)?;
+        if expressions.len() == 1 {
+            Ok(expressions.pop().unwrap())
+        } else {
+            Ok(Value::List(expressions.into()))
+        }
+    }
+
     #[test]
     fn test_one_simple_sexpr() {
         let nodes = parse("(print 5)").unwrap();
         assert_eq!(
             nodes,
-            Value::List(vec![Value::Symbol("print".to_string()), Value::Number(5.),])
+            Value::List(vec![Value::Symbol(Symbol::new("print")), Value::Number(5.),].into())
         );
     }
 
@@ -85,30 +192,48 @@ mod tests {
         let nodes = parse(program).unwrap();
         assert_eq!(
             nodes,
-            Value::List(vec![
-                Value::List(vec![
-                    Value::Symbol("let".to_string()),
-                    Value::Symbol("b".to_string()),
-                    Value::Number(1.0),
-                ]),
-                Value::List(vec![
-                    Value::Symbol("let".to_string()),
-                    Value::Symbol("h".to_string()),
-                    Value::Number(14.),
-                ]),
-                Value::List(vec![
-                    Value::Symbol("print".to_string()),
-                    Value::List(vec![
-                        Value::Symbol("/".to_string()),
-                        Value::List(vec![
-                            Value::Symbol("*".to_string()),
-                            Value::Symbol("b".to_string()),
-                            Value::Symbol("h".to_string()),
-                        ]),
-                        Value::Number(2.),
-                    ]),
-                ]),
-            ])
+            Value::List(
+                vec![
+                    Value::List(
+                        vec![
+                            Value::Symbol(Symbol::new("let")),
+                            Value::Symbol(Symbol::new("b")),
+                            Value::Number(1.0),
+                        ]
+                        .into()
+                    ),
+                    Value::List(
+                        vec![
+                            Value::Symbol(Symbol::new("let")),
+                            Value::Symbol(Symbol::new("h")),
+                            Value::Number(14.),
+                        ]
+                        .into()
+                    ),
+                    Value::List(
+                        vec![
+                            Value::Symbol(Symbol::new("print")),
+                            Value::List(
+                                vec![
+                                    Value::Symbol(Symbol::new("/")),
+                                    Value::List(
+                                        vec![
+                                            Value::Symbol(Symbol::new("*")),
+                                            Value::Symbol(Symbol::new("b")),
+                                            Value::Symbol(Symbol::new("h")),
+                                        ]
+                                        .into()
+                                    ),
+                                    Value::Number(2.),
+                                ]
+                                .into()
+                            ),
+                        ]
+                        .into()
+                    ),
+                ]
+                .into()
+            )
         );
     }
 
@@ -123,34 +248,129 @@ mod tests {
         .unwrap();
         assert_eq!(
             nodes,
-            Value::List(vec![
-                Value::Symbol("cond".to_string()),
-                Value::List(vec![
-                    Value::List(vec![
-                        Value::Symbol("gt".to_string()),
-                        Value::Symbol("x".to_string()),
-                        Value::Number(0.0)
-                    ]),
-                    Value::Symbol("Positive".to_string())
-                ]),
-                Value::List(vec![
-                    Value::List(vec![
-                        Value::Symbol("eq".to_string()),
-                        Value::Symbol("x".to_string()),
-                        Value::Number(0.0)
-                    ]),
-                    Value::Symbol("Zero".to_string())
-                ]),
-                Value::List(vec![
-                Value::List(vec![
-                    Value::Symbol("lt".to_string()),
-                    Value::Symbol("x".to_string()),
-                    Value::Number(0.0)
-                ]),
-                Value::Symbol("Negative".to_string())
-                ]),
-            ])
+            Value::List(
+                vec![
+                    Value::Symbol(Symbol::new("cond")),
+                    Value::List(
+                        vec![
+                            Value::List(
+                                vec![
+                                    Value::Symbol(Symbol::new("gt")),
+                                    Value::Symbol(Symbol::new("x")),
+                                    Value::Number(0.0)
+                                ]
+                                .into()
+                            ),
+                            Value::Symbol(Symbol::new("Positive"))
+                        ]
+                        .into()
+                    ),
+                    Value::List(
+                        vec![
+                            Value::List(
+                                vec![
+                                    Value::Symbol(Symbol::new("eq")),
+                                    Value::Symbol(Symbol::new("x")),
+                                    Value::Number(0.0)
+                                ]
+                                .into()
+                            ),
+                            Value::Symbol(Symbol::new("Zero"))
+                        ]
+                        .into()
+                    ),
+                    Value::List(
+                        vec![
+                            Value::List(
+                                vec![
+                                    Value::Symbol(Symbol::new("lt")),
+                                    Value::Symbol(Symbol::new("x")),
+                                    Value::Number(0.0)
+                                ]
+                                .into()
+                            ),
+                            Value::Symbol(Symbol::new("Negative"))
+                        ]
+                        .into()
+                    ),
+                ]
+                .into()
+            )
         );
     }
-}
 
+    #[test]
+    fn test_quote() {
+        let nodes = parse("'(a ,b)").unwrap();
+        assert_eq!(
+            nodes,
+            Value::List(
+                vec![
+                    Value::Symbol(Symbol::new("quote")),
+                    Value::List(
+                        vec![
+                            Value::Symbol(Symbol::new("a")),
+                            Value::List(
+                                vec![
+                                    Value::Symbol(Symbol::new("unquote")),
+                                    Value::Symbol(Symbol::new("b")),
+                                ]
+                                .into()
+                            ),
+                        ]
+                        .into()
+                    ),
+                ]
+                .into()
+            )
+        );
+    }
+
+    #[test]
+    fn test_map() {
+        let nodes = parse("{a 1 \"b\" (c)}").unwrap();
+        let key = |v: Value| Key::new(&v).unwrap();
+        let map = Map::new()
+            .insert(key(Value::Symbol(Symbol::new("a"))), Value::Number(1.0))
+            .insert(
+                key(Value::String("b".to_string())),
+                Value::List(vec![Value::Symbol(Symbol::new("c"))].into()),
+            );
+        assert_eq!(nodes, Value::Map(Rc::new(map)));
+        assert!(parse("{a}").is_err());
+        assert!(parse("{a 1 a 2}").is_err());
+        assert!(parse("{a 1").is_err());
+    }
+
+    #[test]
+    fn test_vector() {
+        let nodes = parse("[1 (a)]").unwrap();
+        assert_eq!(
+            nodes,
+            Value::Vector(
+                vec![
+                    Value::Number(1.0),
+                    Value::List(vec![Value::Symbol(Symbol::new("a"))].into()),
+                ]
+                .into()
+            )
+        );
+        assert!(parse("[1 2").is_err());
+        assert!(parse("(1 2]").is_err());
+    }
+
+    #[test]
+    fn test_dotted() {
+        let nodes = parse("(1 2 . 3)").unwrap();
+        assert_eq!(nodes.to_string(), "(1 2 . 3)");
+        assert!(matches!(nodes, Value::Pair(_)));
+        let nodes = parse("(1 . (2 3))").unwrap();
+        assert_eq!(
+            nodes,
+            Value::List(vec![Value::Number(1.0), Value::Number(2.0), Value::Number(3.0)].into())
+        );
+        assert!(parse("(. 1)").is_err());
+        assert!(parse("(1 . 2 3)").is_err());
+        assert!(parse("(1 .)").is_err());
+    }
+}
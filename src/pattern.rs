@@ -0,0 +1,175 @@
+use crate::{
+    environment::Env,
+    error::Exception,
+    map::Key,
+    pair,
+    symbol::{known, Symbol},
+    value::Value,
+};
+
+/// Matches `val` against `pattern` and collects the variables it binds into
+/// `bound`. Patterns are:
+///
+/// - `_`, which matches anything
+/// - a symbol, which matches anything and binds it
+/// - `'datum`, a number, string, character or keyword, which match values equal
+///   to them
+/// - `(p ...)` and `(p ... . rest)`, which match lists, the dotted one binding
+///   the items left over to `rest`
+/// - `[p ...]`, which matches vectors of the same length
+/// - `{key p ...}`, which matches maps that have all the keys
+/// - `(type p ...)`, where `type` names a record type, which matches records of
+///   that type field by field
+///
+/// `env` is where record type names are looked up.
+pub fn matches(
+    pattern: &Value,
+    val: &Value,
+    env: &Env,
+    bound: &mut Vec<(Symbol, Value)>,
+) -> Result<bool, Exception> {
+    match pattern {
+        Value::Symbol(known::WILDCARD) => Ok(true),
+        Value::Symbol(s) => {
+            bound.push((*s, val.clone()));
+            Ok(true)
+        }
+        Value::List(l) if l.is_empty() => {
+            Ok(matches!(val, Value::Nil) || matches!(val, Value::List(items) if items.is_empty()))
+        }
+        Value::List(l) => match l.as_slice() {
+            [Value::Symbol(known::QUOTE), datum] => Ok(datum == val),
+            [Value::Symbol(head), fields @ ..] => match env.borrow().get(*head) {
+                Some(Value::RecordType(rtype)) => {
+                    if fields.len() != rtype.fields.len() {
+                        return Err(Exception::new(
+                            "syntax-error",
+                            format!(
+                                "{} has {} fields, the pattern {} has {}",
+                                rtype.display_name(),
+                                rtype.fields.len(),
+                                pattern,
+                                fields.len()
+                            ),
+                        ));
+                    }
+                    match val {
+                        Value::Record(r) if std::rc::Rc::ptr_eq(&r.rtype, &rtype) => {
+                            let vals = r.fields.borrow().clone();
+                            all(fields, &vals, env, bound)
+                        }
+                        _ => Ok(false),
+                    }
+                }
+                _ => list(l, None, val, env, bound),
+            },
+            _ => list(l, None, val, env, bound),
+        },
+        Value::Pair(_) => {
+            let (items, rest) = dotted(pattern);
+            list(&items, Some(&rest), val, env, bound)
+        }
+        Value::Vector(patterns) => match val {
+            Value::Vector(v) => {
+                let patterns = patterns.iter().cloned().collect::<Vec<_>>();
+                let vals = v.iter().cloned().collect::<Vec<_>>();
+                if patterns.len() != vals.len() {
+                    return Ok(false);
+                }
+                all(&patterns, &vals, env, bound)
+            }
+            _ => Ok(false),
+        },
+        Value::Map(patterns) => {
+            let map = match val {
+                Value::Map(m) => m,
+                _ => return Ok(false),
+            };
+            for (key, p) in patterns.iter() {
+                let key = match key {
+                    Value::List(l) if matches!(l.first(), Some(Value::Symbol(known::QUOTE))) => {
+                        l.get(1).cloned().unwrap_or(Value::Nil)
+                    }
+                    _ => key.clone(),
+                };
+                let found = Key::new(&key).and_then(|key| map.get(&key).cloned());
+                match found {
+                    Some(v) if matches(p, &v, env, bound)? => {}
+                    _ => return Ok(false),
+                }
+            }
+            Ok(true)
+        }
+        _ => Ok(pattern == val),
+    }
+}
+
+/// Binds the variables of `pattern` in `env`, or raises a `match-error` if
+/// `val` does not match it.
+pub fn bind(pattern: &Value, val: &Value, env: &Env) -> Result<(), Exception> {
+    if let Value::Symbol(s) = pattern {
+        env.borrow_mut().set(*s, val.clone());
+        return Ok(());
+    }
+    let mut bound = vec![];
+    if !matches(pattern, val, env, &mut bound)? {
+        return Err(Exception::with_data(
+            "match-error",
+            format!("{} does not match {}", val, pattern),
+            val.clone(),
+        ));
+    }
+    let mut env = env.borrow_mut();
+    for (name, val) in bound {
+        env.set(name, val);
+    }
+    Ok(())
+}
+
+fn all(
+    patterns: &[Value],
+    vals: &[Value],
+    env: &Env,
+    bound: &mut Vec<(Symbol, Value)>,
+) -> Result<bool, Exception> {
+    for (p, v) in patterns.iter().zip(vals) {
+        if !matches(p, v, env, bound)? {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+/// Matches the items of a list pattern one by one, and the rest of the list
+/// against `rest` if the pattern is dotted.
+fn list(
+    patterns: &[Value],
+    rest: Option<&Value>,
+    val: &Value,
+    env: &Env,
+    bound: &mut Vec<(Symbol, Value)>,
+) -> Result<bool, Exception> {
+    let mut val = val.clone();
+    for p in patterns {
+        match pair::uncons(&val) {
+            Some((item, tail)) if matches(p, &item, env, bound)? => val = tail,
+            _ => return Ok(false),
+        }
+    }
+    match rest {
+        Some(rest) => matches(rest, &val, env, bound),
+        None => Ok(matches!(&val, Value::List(l) if l.is_empty())),
+    }
+}
+
+/// The items of a dotted pattern and the pattern after the dot.
+fn dotted(pattern: &Value) -> (Vec<Value>, Value) {
+    let mut items = vec![];
+    let mut rest = pattern.clone();
+    while let Value::Pair(p) = rest {
+        items.push(p.car.borrow().clone());
+        let next = p.cdr.borrow().clone();
+        rest = next;
+    }
+    (items, rest)
+}
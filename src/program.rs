@@ -1,293 +1,3130 @@
-use std::{cell::RefCell, rc::Rc};
+use std::{
+    cell::{Cell, RefCell},
+    collections::HashSet,
+    fmt, ptr,
+    rc::Rc,
+};
+
+use crate::{
+    builtins, conditions,
+    environment::{Env, Environment},
+    error::Exception,
+    generator::{self, Generator},
+    list::List,
+    macros,
+    map::{Key, Map},
+    pair,
+    parser::parse_program,
+    pattern,
+    promise::Promise,
+    record::{self, RecordType},
+    stack::Stack,
+    symbol::{known, Symbol},
+    syntax_rules::SyntaxRules,
+    value::{Primitive, Value},
+    vector::Vector,
+};
+
+pub fn evaluate(source: &str, env: &mut Env) -> Result<Value, Exception> {
+    let mut expressions =
+        parse_program(source).map_err(|e| Exception::new("parse-error", e.to_string()))?;
+    if expressions.len() == 1 {
+        return top_level(&expressions.pop().unwrap(), env);
+    }
+    let mut results = vec![];
+    for expression in &expressions {
+        match top_level(expression, env)? {
+            Value::Nil => {}
+            res => results.push(res),
+        }
+    }
+    Ok(Value::List(results.into()))
+}
+
+/// Each top level expression is macro expanded right before it is evaluated, so
+/// it can use the macros defined by the expressions before it.
+fn top_level(expression: &Value, env: &mut Env) -> Result<Value, Exception> {
+    let expanded = macros::expand(expression, env)?;
+    run(Tail::Eval(expanded, env.clone()))
+}
+
+/// What evaluation goes on with after a step: a value for the innermost frame of
+/// the continuation, an expression to evaluate or a function to call. Forms and
+/// control builtins never evaluate anything themselves, they return a `Tail` and
+/// push frames for what should happen with its value.
+pub enum Tail {
+    Return(Value),
+    Eval(Value, Env),
+    Call(Value, Vec<Value>),
+    /// Pushes a frame onto the continuation and goes on with the tail.
+    Push(Frame, Box<Tail>),
+    /// Calls the function with the current continuation.
+    Capture(Value),
+    /// Removes the frames up to the innermost prompt and calls the function
+    /// with them as a continuation.
+    Shift(Value),
+    /// Suspends the innermost generator with the value.
+    Yield(Value),
+}
+
+pub type Then = Rc<dyn Fn(Value) -> Result<Tail, Exception>>;
+
+/// One step of the rest of a computation.
+#[derive(Clone)]
+pub enum Frame {
+    /// Goes on with the value. A continuation can resume the same frame any
+    /// number of times, so it must not consume or mutate what it captured.
+    Then(Then),
+    /// Like `Then`, but goes on with all of multiple values instead of the
+    /// first one.
+    Values(Then),
+    /// Restores the handlers and restarts in effect outside of a form.
+    Dynamic(conditions::State),
+    /// The `catch` clause of a `try`, as variable, handler body and scope.
+    Catch(Symbol, Rc<[Value]>, Env),
+    /// The `finally` clause of a `try`, which runs when the body returns or
+    /// raises, but not when a continuation jumps out of it.
+    Finally(Rc<[Value]>, Env),
+    /// The restarts of a `restart-case` and the id of the first one.
+    Restarts(usize, Rc<[Value]>),
+    /// The before and after thunks of a `dynamic-wind`.
+    Wind(Value, Value),
+    /// The delimiter of a `reset`.
+    Prompt,
+    /// A generator that `next` resumed, and the value to return if it is done.
+    Generator(Rc<Generator>, Option<Value>),
+    /// One iteration of a loop, and what goes on with the next one when it
+    /// finishes or `continue` is called.
+    Loop(Then),
+}
+
+/// Evaluates `tail`, then goes on with `f` of its value.
+pub fn then(tail: Tail, f: impl Fn(Value) -> Result<Tail, Exception> + 'static) -> Tail {
+    push(Frame::Then(Rc::new(f)), tail)
+}
+
+pub fn push(frame: Frame, tail: Tail) -> Tail {
+    Tail::Push(frame, Box::new(tail))
+}
+
+/// A continuation captured by `call/cc`. Calling it with a value abandons the
+/// continuation of the call and returns the value to this one instead.
+///
+/// A continuation captured by `shift` only holds the frames up to the innermost
+/// `reset`. Calling it pushes them onto the continuation of the call instead of
+/// replacing it, so it returns like a function.
+pub struct Continuation {
+    frames: Stack<Frame>,
+    state: conditions::State,
+    run: usize,
+    delimited: bool,
+}
+
+impl PartialEq for Continuation {
+    fn eq(&self, other: &Self) -> bool {
+        ptr::eq(self, other)
+    }
+}
+
+impl fmt::Debug for Continuation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Continuation({})", self.run)
+    }
+}
+
+thread_local! {
+    /// The ids of the runs in progress, innermost last. Every top level run has
+    /// id 0, so a continuation captured while evaluating one expression can be
+    /// resumed from a later one.
+    static RUNS: RefCell<Vec<usize>> = const { RefCell::new(vec![]) };
+    static NEXT_RUN: Cell<usize> = const { Cell::new(1) };
+}
+
+/// Evaluates `tail` with an empty continuation. Rust code that calls back into
+/// Lisp, like macro expanders and condition handlers, starts a nested run.
+fn run(tail: Tail) -> Result<Value, Exception> {
+    let id = RUNS.with(|runs| {
+        let mut runs = runs.borrow_mut();
+        let id = match runs.is_empty() {
+            true => 0,
+            false => NEXT_RUN.with(|next| next.replace(next.get() + 1)),
+        };
+        runs.push(id);
+        id
+    });
+    let result = run_frames(tail, id);
+    let outermost = RUNS.with(|runs| {
+        let mut runs = runs.borrow_mut();
+        runs.pop();
+        runs.is_empty()
+    });
+    // A nested run hands a raised value back to a step of the run around it,
+    // which must not signal it again.
+    match result {
+        Err(Exception::Unwind(val)) if outermost => Err(Exception::Raise(val)),
+        result => result,
+    }
+}
+
+fn run_frames(mut tail: Tail, run: usize) -> Result<Value, Exception> {
+    let mut frames = Stack::new();
+    loop {
+        let next = match tail {
+            Tail::Return(val) => match frames.pop() {
+                Some(frame) => resume(frame, val),
+                None => return Ok(val),
+            },
+            Tail::Eval(node, mut env) => match node {
+                Value::Symbol(s) => symbol(s, &env).map(Tail::Return),
+                Value::List(l) => list(&l, &mut env),
+                Value::Map(m) => map_literal(&m, &env),
+                Value::Vector(v) => vector_literal(&v, &env),
+                Value::Pair(_) => match pair::proper_list(&node) {
+                    Some(l) => list(&l, &mut env),
+                    None => Err(Exception::new(
+                        "syntax-error",
+                        format!("Cannot evaluate the improper list {}", node),
+                    )),
+                },
+                node => Ok(Tail::Return(node)),
+            },
+            Tail::Call(Value::Continuation(k), args) => {
+                resume_continuation(&mut frames, k, args, run)
+            }
+            Tail::Call(func, args) => call_tail(&func, &args),
+            Tail::Push(frame, tail) => {
+                frames = frames.push(frame);
+                Ok(*tail)
+            }
+            Tail::Capture(func) => {
+                let k = Continuation {
+                    frames: frames.clone(),
+                    state: conditions::current(),
+                    run,
+                    delimited: false,
+                };
+                Ok(Tail::Call(func, vec![Value::Continuation(Rc::new(k))]))
+            }
+            Tail::Shift(func) => shift(&mut frames, func, run),
+            Tail::Yield(val) => yield_value(&mut frames, val, run),
+        };
+        tail = match next {
+            Ok(tail) => tail,
+            Err(e) => unwind(&mut frames, signal(e), run)?,
+        };
+    }
+}
+
+/// Signals a value raised by the last step to the active handlers. Steps raise
+/// by returning, so whatever raised it no longer holds on to the data it was
+/// working on when the handlers run. A handler can take over by raising or
+/// invoking a restart.
+fn signal(e: Exception) -> Exception {
+    match e {
+        Exception::Raise(val) => match conditions::signal(&val, true) {
+            Ok(()) => Exception::Unwind(val),
+            Err(e) => signal(e),
+        },
+        e => e,
+    }
+}
+
+/// Hands a value to a frame that was popped off the continuation.
+fn resume(frame: Frame, val: Value) -> Result<Tail, Exception> {
+    match frame {
+        Frame::Then(f) => f(val.single()),
+        Frame::Values(f) => f(val),
+        Frame::Dynamic(state) => {
+            conditions::restore(state);
+            Ok(Tail::Return(val))
+        }
+        Frame::Catch(..) | Frame::Restarts(..) | Frame::Prompt => Ok(Tail::Return(val)),
+        Frame::Finally(cleanup, env) => Ok(then(body(&cleanup, &env), move |_| {
+            Ok(Tail::Return(val.clone()))
+        })),
+        Frame::Wind(_, after) => Ok(then(Tail::Call(after, vec![]), move |_| {
+            Ok(Tail::Return(val.clone()))
+        })),
+        Frame::Generator(generator, default) => {
+            generator.finish();
+            generator::exhausted(default)
+        }
+        Frame::Loop(next) => next(val),
+    }
+}
+
+/// Pops frames until one of them handles `e`. Returns `e` if none of them does.
+fn unwind(frames: &mut Stack<Frame>, mut e: Exception, run: usize) -> Result<Tail, Exception> {
+    loop {
+        if let Exception::Throw(k, val) = &e {
+            if k.run == run {
+                match jump(frames, k, val.clone()) {
+                    Ok(tail) => return Ok(tail),
+                    Err(next) => {
+                        e = next;
+                        continue;
+                    }
+                }
+            }
+        }
+        let frame = match frames.pop() {
+            Some(frame) => frame,
+            None => return Err(e),
+        };
+        match (frame, &e) {
+            (Frame::Dynamic(state), _) => conditions::restore(state),
+            (Frame::Catch(name, handler, env), Exception::Unwind(raised)) => {
+                let handler_env = Rc::new(RefCell::new(Environment::extend(env)));
+                handler_env.borrow_mut().set(name, raised.clone());
+                return Ok(body(&handler, &handler_env));
+            }
+            (Frame::Finally(cleanup, env), _) => {
+                return Ok(then(body(&cleanup, &env), move |_| Err(e.clone())));
+            }
+            (Frame::Restarts(first, restarts), Exception::Restart(id, args))
+                if (first..first + restarts.len()).contains(id) =>
+            {
+                return Ok(Tail::Call(restarts[id - first].clone(), args.clone()));
+            }
+            (Frame::Generator(generator, _), _) => generator.finish(),
+            (Frame::Loop(next), Exception::Continue) => return next(Value::Nil),
+            (Frame::Loop(_), Exception::Break(val)) => return Ok(Tail::Return(val.clone())),
+            (Frame::Wind(_, after), _) => {
+                return Ok(then(Tail::Call(after, vec![]), move |_| Err(e.clone())));
+            }
+            _ => {}
+        }
+    }
+}
+
+fn resume_continuation(
+    frames: &mut Stack<Frame>,
+    k: Rc<Continuation>,
+    args: Vec<Value>,
+    run: usize,
+) -> Result<Tail, Exception> {
+    // Calling a continuation with several arguments returns them as multiple
+    // values.
+    let val = match args.as_slice() {
+        [] => Value::Nil,
+        [val] => val.clone(),
+        _ => Value::Values(args.into()),
+    };
+    if k.delimited {
+        compose(&k, val)
+    } else if k.run == run {
+        jump(frames, &k, val)
+    } else if RUNS.with(|runs| runs.borrow().contains(&k.run)) {
+        Err(Exception::Throw(k, val))
+    } else {
+        Err(Exception::new(
+            "control-error",
+            "The evaluation the continuation was captured in has finished",
+        ))
+    }
+}
+
+/// Replaces the continuation with `k`. The after thunks of the `dynamic-wind`
+/// frames that are left run first, innermost first, then the before thunks of
+/// the ones that are entered, outermost first.
+fn jump(frames: &mut Stack<Frame>, k: &Continuation, val: Value) -> Result<Tail, Exception> {
+    let target = k
+        .frames
+        .nodes()
+        .map(|(node, _)| node)
+        .collect::<HashSet<_>>();
+    let current = frames.nodes().map(|(node, _)| node).collect::<HashSet<_>>();
+    let exits = frames
+        .nodes()
+        .take_while(|(node, _)| !target.contains(node))
+        .filter_map(|(_, frame)| match frame {
+            Frame::Wind(_, after) => Some(after.clone()),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    let mut entries = k
+        .frames
+        .nodes()
+        .take_while(|(node, _)| !current.contains(node))
+        .filter_map(|(_, frame)| match frame {
+            Frame::Wind(before, _) => Some(before.clone()),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    entries.reverse();
+    for thunk in exits.iter().chain(&entries) {
+        apply(thunk, &[])?;
+    }
+    *frames = k.frames.clone();
+    conditions::restore(k.state.clone());
+    Ok(Tail::Return(val))
+}
+
+fn shift(frames: &mut Stack<Frame>, func: Value, run: usize) -> Result<Tail, Exception> {
+    let k = delimit(frames, run, |frame| matches!(frame, Frame::Prompt))
+        .ok_or_else(|| Exception::new("control-error", "shift must be used inside of a reset"))?;
+    Ok(Tail::Call(func, vec![Value::Continuation(Rc::new(k))]))
+}
+
+/// Suspends the innermost generator and returns the value from its `next`.
+fn yield_value(frames: &mut Stack<Frame>, val: Value, run: usize) -> Result<Tail, Exception> {
+    let k = delimit(frames, run, |frame| matches!(frame, Frame::Generator(..)))
+        .ok_or_else(|| Exception::new("control-error", "yield must be called from a generator"))?;
+    if let Some(Frame::Generator(generator, _)) = frames.pop() {
+        generator.suspend(k);
+    }
+    Ok(Tail::Return(val))
+}
+
+/// Removes the frames above the innermost delimiter and returns them as a
+/// delimited continuation, or `None` if there is no delimiter.
+fn delimit(
+    frames: &mut Stack<Frame>,
+    run: usize,
+    is_delimiter: impl Fn(&Frame) -> bool,
+) -> Option<Continuation> {
+    let depth = frames.iter().position(is_delimiter)?;
+    let mut captured = (0..depth).filter_map(|_| frames.pop()).collect::<Vec<_>>();
+    let mut segment = Stack::new();
+    while let Some(frame) = captured.pop() {
+        segment = segment.push(frame);
+    }
+    Some(Continuation {
+        frames: segment,
+        state: conditions::current(),
+        run,
+        delimited: true,
+    })
+}
+
+/// Pushes the frames of a delimited continuation, inside a new prompt, onto the
+/// current continuation.
+fn compose(k: &Continuation, val: Value) -> Result<Tail, Exception> {
+    let outer = conditions::current();
+    Ok(push(
+        Frame::Dynamic(outer),
+        push(Frame::Prompt, reinstate(k, val)),
+    ))
+}
+
+/// Goes on with the frames of a delimited continuation pushed onto the current
+/// continuation as they are.
+pub fn reinstate(k: &Continuation, val: Value) -> Tail {
+    conditions::restore(k.state.clone());
+    k.frames
+        .iter()
+        .fold(Tail::Return(val), |tail, frame| push(frame.clone(), tail))
+}
+
+fn symbol(s: Symbol, env: &Env) -> Result<Value, Exception> {
+    let found = env.borrow().get(s);
+    if let Some(val) = found {
+        Ok(val)
+    } else if let Some(builtin) = builtins::lookup(s.original()) {
+        Ok(builtin)
+    } else {
+        Err(Exception::new(
+            "unbound-variable",
+            format!("Unbound symbol {}", s),
+        ))
+    }
+}
+
+fn list(list: &[Value], env: &mut Env) -> Result<Tail, Exception> {
+    let head = match list.first() {
+        Some(head) => head,
+        None => return Ok(Tail::Return(Value::Nil)),
+    };
+    if let Value::Symbol(s) = head {
+        match *s {
+            known::DEFINE => return define(list, env),
+            known::SET => return set(list, env),
+            known::LET => return _let(list, env),
+            known::LET_STAR => return let_star(list, env),
+            known::LETREC => return letrec(list, env),
+            known::RECEIVE => return receive(list, env),
+            known::LET_VALUES => return let_values(list, env),
+            known::FN => return _fn(list, env).map(Tail::Return),
+            known::DEFMACRO => return defmacro(list, env).map(Tail::Return),
+            known::DEFINE_SYNTAX => return define_syntax(list, env).map(Tail::Return),
+            known::DEFSTRUCT => return defstruct(list, env).map(Tail::Return),
+            known::DEFINE_RECORD_TYPE => return define_record_type(list, env).map(Tail::Return),
+            known::MACROEXPAND_1 => return macroexpand(list, env, false),
+            known::MACROEXPAND => return macroexpand(list, env, true),
+            known::QUOTE => return quote(list).map(Tail::Return),
+            known::QUASIQUOTE => return quasiquote(list, env),
+            known::COND => return cond(list, env),
+            known::CASE => return case(list, env),
+            known::MATCH => return _match(list, env),
+            known::IF => return _if(list, env),
+            known::WHEN => return when(list, env, true),
+            known::UNLESS => return when(list, env, false),
+            known::AND => return short_circuit(list[1..].into(), env.clone(), false),
+            known::OR => return short_circuit(list[1..].into(), env.clone(), true),
+            known::TRY => return try_catch(list, env),
+            known::HANDLER_BIND => return handler_bind(list, env),
+            known::RESTART_CASE => return restart_case(list, env),
+            known::RESET => return Ok(push(Frame::Prompt, body(&list[1..], env))),
+            known::SHIFT => return shift_form(list, env),
+            known::WHILE => return _while(list, env),
+            known::DOTIMES => return dotimes(list, env),
+            known::DOLIST => return dolist(list, env),
+            known::LOOP => return Ok(_loop(list[1..].into(), env.clone())),
+            known::DELAY => return delay(list, env),
+            known::STREAM_CONS => return stream_cons(list, env),
+            _ => {}
+        }
+    }
+    call(list, env)
+}
+
+/// Evaluates `exprs` from left to right, then goes on with `f` of their values.
+fn eval_all(
+    exprs: Rc<[Value]>,
+    env: &Env,
+    f: impl Fn(Vec<Value>) -> Result<Tail, Exception> + 'static,
+) -> Result<Tail, Exception> {
+    eval_from(exprs, vec![], env.clone(), Rc::new(f))
+}
+
+type Finish = Rc<dyn Fn(Vec<Value>) -> Result<Tail, Exception>>;
+
+fn eval_from(
+    exprs: Rc<[Value]>,
+    mut done: Vec<Value>,
+    env: Env,
+    f: Finish,
+) -> Result<Tail, Exception> {
+    while let Some(expr) = exprs.get(done.len()) {
+        match expr {
+            // Only lists, maps and vectors need a frame, symbols and
+            // constants are looked up right away.
+            Value::List(_) | Value::Pair(_) | Value::Map(_) | Value::Vector(_) => {
+                let expr = expr.clone();
+                return Ok(then(Tail::Eval(expr, env.clone()), move |val| {
+                    let mut done = done.clone();
+                    done.push(val);
+                    eval_from(exprs.clone(), done, env.clone(), f.clone())
+                }));
+            }
+            Value::Symbol(s) => done.push(symbol(*s, &env)?),
+            _ => done.push(expr.clone()),
+        }
+    }
+    f(done)
+}
+
+/// Evaluates the keys and values of a map literal into a new map.
+fn map_literal(map: &Map, env: &Env) -> Result<Tail, Exception> {
+    let exprs = map
+        .iter()
+        .flat_map(|(key, val)| [key.clone(), val.clone()])
+        .collect::<Rc<[_]>>();
+    eval_all(exprs, env, |vals| {
+        let mut map = Map::new();
+        for pair in vals.chunks(2) {
+            let key = Key::new(&pair[0]).ok_or_else(|| {
+                Exception::new("type-error", format!("Invalid map key {}", pair[0]))
+            })?;
+            map = map.insert(key, pair[1].clone());
+        }
+        Ok(Tail::Return(Value::Map(Rc::new(map))))
+    })
+}
+
+/// Evaluates the items of a vector literal into a new vector.
+fn vector_literal(items: &Vector<Value>, env: &Env) -> Result<Tail, Exception> {
+    eval_all(items.iter().cloned().collect(), env, |vals| {
+        Ok(Tail::Return(Value::Vector(vals.into())))
+    })
+}
+
+fn define(list: &[Value], env: &mut Env) -> Result<Tail, Exception> {
+    if list.len() != 3 {
+        return Err(Exception::new(
+            "syntax-error",
+            "Invalid number of arguments for define",
+        ));
+    }
+    let symbol = match &list[1] {
+        Value::Symbol(s) => *s,
+        _ => return Err(Exception::new("syntax-error", "Invalid define")),
+    };
+    let env = env.clone();
+    Ok(then(Tail::Eval(list[2].clone(), env.clone()), move |val| {
+        env.borrow_mut().set(symbol, val);
+        Ok(Tail::Return(Value::Nil))
+    }))
+}
+
+fn set(list: &[Value], env: &mut Env) -> Result<Tail, Exception> {
+    if list.len() != 3 {
+        return Err(Exception::new(
+            "syntax-error",
+            "Invalid number of arguments for set!",
+        ));
+    }
+    let symbol = match &list[1] {
+        Value::Symbol(s) => *s,
+        _ => return Err(Exception::new("syntax-error", "Invalid set!")),
+    };
+    let env = env.clone();
+    Ok(then(Tail::Eval(list[2].clone(), env.clone()), move |val| {
+        env.borrow_mut().assign(symbol, val)?;
+        Ok(Tail::Return(Value::Nil))
+    }))
+}
+
+/// Splits `((pattern init) ...)` into its patterns and init expressions.
+fn bindings(node: &Value) -> Result<(Vec<Value>, Vec<Value>), Exception> {
+    let list = match node {
+        Value::List(l) => l,
+        _ => {
+            return Err(Exception::new(
+                "syntax-error",
+                format!("Invalid bindings {}", node),
+            ))
+        }
+    };
+    let mut names = vec![];
+    let mut inits = vec![];
+    for binding in list {
+        match binding {
+            Value::List(b) => match b.as_slice() {
+                [pattern, init] => {
+                    names.push(pattern.clone());
+                    inits.push(init.clone());
+                }
+                _ => {
+                    return Err(Exception::new(
+                        "syntax-error",
+                        format!("Invalid binding {}", binding),
+                    ))
+                }
+            },
+            _ => {
+                return Err(Exception::new(
+                    "syntax-error",
+                    format!("Invalid binding {}", binding),
+                ))
+            }
+        }
+    }
+    Ok((names, inits))
+}
+
+/// The names of bindings that cannot be patterns.
+fn plain_names(patterns: Vec<Value>) -> Result<Vec<Symbol>, Exception> {
+    patterns
+        .into_iter()
+        .map(|pattern| match pattern {
+            Value::Symbol(name) => Ok(name),
+            _ => Err(Exception::new(
+                "syntax-error",
+                format!("Invalid binding name {}", pattern),
+            )),
+        })
+        .collect()
+}
+
+/// `(let ((pattern init) ...) body ...)` evaluates every init in the enclosing
+/// scope and destructures it, `(let name ((param init) ...) body ...)` also
+/// binds `name` to a function of the params so the body can loop by calling it.
+fn _let(list: &[Value], env: &mut Env) -> Result<Tail, Exception> {
+    if list.len() < 3 {
+        return Err(Exception::new(
+            "syntax-error",
+            "Invalid number of arguments for let",
+        ));
+    }
+    let outer = env.clone();
+    if let Value::Symbol(name) = &list[1] {
+        let name = *name;
+        let (params, inits) = bindings(&list[2])?;
+        let params = plain_names(params)?;
+        let exprs = list[3..].to_vec();
+        return eval_all(inits.into(), env, move |args| {
+            let loop_env = Rc::new(RefCell::new(Environment::extend(outer.clone())));
+            let func = Value::Lambda(params.clone(), exprs.clone(), loop_env.clone());
+            loop_env.borrow_mut().set(name, func.clone());
+            Ok(Tail::Call(func, args))
+        });
+    }
+    let (names, inits) = bindings(&list[1])?;
+    let exprs = list[2..].to_vec();
+    eval_all(inits.into(), env, move |vals| {
+        let new_env = Rc::new(RefCell::new(Environment::extend(outer.clone())));
+        for (pattern, val) in names.iter().zip(vals) {
+            pattern::bind(pattern, &val, &new_env)?;
+        }
+        Ok(body(&exprs, &new_env))
+    })
+}
+
+/// Like `let`, but every init can see the bindings before it.
+fn let_star(list: &[Value], env: &mut Env) -> Result<Tail, Exception> {
+    if list.len() < 3 {
+        return Err(Exception::new(
+            "syntax-error",
+            "Invalid number of arguments for let*",
+        ));
+    }
+    let (names, inits) = bindings(&list[1])?;
+    let_star_from(names.into(), inits.into(), env.clone(), list[2..].into())
+}
+
+/// Binds the first of `names` in a new scope and goes on with the rest there.
+fn let_star_from(
+    names: Rc<[Value]>,
+    inits: Rc<[Value]>,
+    env: Env,
+    exprs: Rc<[Value]>,
+) -> Result<Tail, Exception> {
+    if names.is_empty() {
+        let body_env = Rc::new(RefCell::new(Environment::extend(env)));
+        return Ok(body(&exprs, &body_env));
+    }
+    Ok(then(
+        Tail::Eval(inits[0].clone(), env.clone()),
+        move |val| {
+            let new_env = Rc::new(RefCell::new(Environment::extend(env.clone())));
+            pattern::bind(&names[0], &val, &new_env)?;
+            let_star_from(names[1..].into(), inits[1..].into(), new_env, exprs.clone())
+        },
+    ))
+}
+
+/// Like `let`, but every init is evaluated in the new scope, so functions
+/// bound by it can refer to each other.
+fn letrec(list: &[Value], env: &mut Env) -> Result<Tail, Exception> {
+    if list.len() < 3 {
+        return Err(Exception::new(
+            "syntax-error",
+            "Invalid number of arguments for letrec",
+        ));
+    }
+    let (names, inits) = bindings(&list[1])?;
+    let names = plain_names(names)?;
+    let new_env = Rc::new(RefCell::new(Environment::extend(env.clone())));
+    for name in &names {
+        new_env.borrow_mut().set(*name, Value::Nil);
+    }
+    letrec_from(names.into(), inits.into(), new_env, list[2..].into())
+}
+
+/// Evaluates the first of `inits`, binds it and goes on with the rest.
+fn letrec_from(
+    names: Rc<[Symbol]>,
+    inits: Rc<[Value]>,
+    env: Env,
+    exprs: Rc<[Value]>,
+) -> Result<Tail, Exception> {
+    if names.is_empty() {
+        return Ok(body(&exprs, &env));
+    }
+    Ok(then(
+        Tail::Eval(inits[0].clone(), env.clone()),
+        move |val| {
+            env.borrow_mut().set(names[0], val);
+            letrec_from(
+                names[1..].into(),
+                inits[1..].into(),
+                env.clone(),
+                exprs.clone(),
+            )
+        },
+    ))
+}
+
+/// The parameters that receive multiple values: a list of names like the
+/// parameters of a function, or a single name that gets all of them as a list.
+fn formals(node: &Value) -> Result<Rc<[Symbol]>, Exception> {
+    match node {
+        Value::Symbol(rest) => Ok(vec![known::REST, *rest].into()),
+        Value::List(l) => {
+            let names = plain_names(l.iter().cloned().collect())?;
+            match names.iter().position(|name| *name == known::REST) {
+                Some(i) if i + 2 != names.len() => Err(Exception::new(
+                    "syntax-error",
+                    "&rest must be followed by exactly one argument",
+                )),
+                _ => Ok(names.into()),
+            }
+        }
+        _ => Err(Exception::new(
+            "syntax-error",
+            format!("Invalid formals {}", node),
+        )),
+    }
+}
+
+/// `(receive formals expr body ...)` binds the values of `expr` to `formals`
+/// and evaluates the body with them.
+fn receive(list: &[Value], env: &mut Env) -> Result<Tail, Exception> {
+    if list.len() < 4 {
+        return Err(Exception::new(
+            "syntax-error",
+            "Invalid number of arguments for receive",
+        ));
+    }
+    let params = formals(&list[1])?;
+    let exprs = list[3..].to_vec();
+    let outer = env.clone();
+    let receive = move |vals: Value| {
+        let new_env = Rc::new(RefCell::new(Environment::extend(outer.clone())));
+        bind(&params, &vals.spread(), &new_env)?;
+        Ok(body(&exprs, &new_env))
+    };
+    Ok(push(
+        Frame::Values(Rc::new(receive)),
+        Tail::Eval(list[2].clone(), env.clone()),
+    ))
+}
+
+/// `(let-values ((formals init) ...) body ...)` is like `let`, but binds all
+/// the values of every init to its formals.
+fn let_values(list: &[Value], env: &mut Env) -> Result<Tail, Exception> {
+    if list.len() < 3 {
+        return Err(Exception::new(
+            "syntax-error",
+            "Invalid number of arguments for let-values",
+        ));
+    }
+    let (names, inits) = bindings(&list[1])?;
+    let params = names.iter().map(formals).collect::<Result<Rc<[_]>, _>>()?;
+    let new_env = Rc::new(RefCell::new(Environment::extend(env.clone())));
+    let_values_from(params, inits.into(), env.clone(), new_env, list[2..].into())
+}
+
+/// Evaluates the first of `inits` in the enclosing scope, binds its values in
+/// the new one and goes on with the rest.
+fn let_values_from(
+    params: Rc<[Rc<[Symbol]>]>,
+    inits: Rc<[Value]>,
+    outer: Env,
+    env: Env,
+    exprs: Rc<[Value]>,
+) -> Result<Tail, Exception> {
+    if params.is_empty() {
+        return Ok(body(&exprs, &env));
+    }
+    let init = Tail::Eval(inits[0].clone(), outer.clone());
+    let next = move |vals: Value| {
+        bind(&params[0], &vals.spread(), &env)?;
+        let_values_from(
+            params[1..].into(),
+            inits[1..].into(),
+            outer.clone(),
+            env.clone(),
+            exprs.clone(),
+        )
+    };
+    Ok(push(Frame::Values(Rc::new(next)), init))
+}
+
+fn _fn(list: &[Value], env: &mut Env) -> Result<Value, Exception> {
+    if list.len() < 3 {
+        return Err(Exception::new("syntax-error", "Invalid function"));
+    }
+    // Parameters that are patterns get a fresh name, and the body starts by
+    // destructuring the argument bound to it.
+    let mut patterns = vec![];
+    let args = match &list[1] {
+        Value::List(l) => {
+            let mut args = vec![];
+            for arg in l {
+                match arg {
+                    Value::Symbol(s) => args.push(*s),
+                    Value::List(_) | Value::Pair(_) | Value::Vector(_) | Value::Map(_)
+                        if !args.contains(&known::KEY) =>
+                    {
+                        let name = Symbol::gensym("arg");
+                        patterns.push(Value::List(vec![arg.clone(), Value::Symbol(name)].into()));
+                        args.push(name);
+                    }
+                    _ => return Err(Exception::new("syntax-error", "Invalid function argument")),
+                }
+            }
+            args
+        }
+        _ => return Err(Exception::new("syntax-error", "Invalid function")),
+    };
+    if let Some(i) = args.iter().position(|arg| *arg == known::REST) {
+        if i + 2 != args.len() {
+            return Err(Exception::new(
+                "syntax-error",
+                "&rest must be followed by exactly one argument",
+            ));
+        }
+    }
+    if let Some(i) = args.iter().position(|arg| *arg == known::KEY) {
+        if args.contains(&known::REST) || args[i + 1..].contains(&known::KEY) {
+            return Err(Exception::new(
+                "syntax-error",
+                "&key must be followed by plain arguments and cannot be used with &rest",
+            ));
+        }
+    }
+    let exprs = if patterns.is_empty() {
+        list[2..].to_vec()
+    } else {
+        let mut destructure = vec![
+            Value::Symbol(Symbol::new("let")),
+            Value::List(patterns.into()),
+        ];
+        destructure.extend(list[2..].iter().cloned());
+        vec![Value::List(destructure.into())]
+    };
+    Ok(Value::Lambda(args, exprs, env.clone()))
+}
+
+/// `(defmacro name (param ...) body ...)` binds `name` to a function from the
+/// unevaluated arguments of a call to the code that replaces the call.
+fn defmacro(list: &[Value], env: &mut Env) -> Result<Value, Exception> {
+    if list.len() < 4 {
+        return Err(Exception::new(
+            "syntax-error",
+            "Invalid number of arguments for defmacro",
+        ));
+    }
+    let symbol = match &list[1] {
+        Value::Symbol(s) => *s,
+        _ => return Err(Exception::new("syntax-error", "Invalid defmacro")),
+    };
+    // Everything after the name has the same shape as a `fn` form.
+    let expander = _fn(&list[1..], env)?;
+    env.borrow_mut()
+        .set(symbol, Value::Macro(Box::new(expander)));
+    Ok(Value::Nil)
+}
+
+/// `(defstruct name field ...)` defines a record type and binds `name` to it,
+/// along with `make-name`, `name?`, and `name-field` and `set-name-field!` for
+/// every field.
+fn defstruct(list: &[Value], env: &mut Env) -> Result<Value, Exception> {
+    let names = list
+        .get(1..)
+        .unwrap_or(&[])
+        .iter()
+        .map(|v| match v {
+            Value::Symbol(s) => Ok(*s),
+            _ => Err(Exception::new(
+                "syntax-error",
+                format!("Invalid defstruct {}", v),
+            )),
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    let (name, fields) = match names.split_first() {
+        Some(split) => split,
+        None => return Err(Exception::new("syntax-error", "defstruct expects a name")),
+    };
+    let rtype = Rc::new(RecordType {
+        name: *name,
+        fields: fields.to_vec(),
+    });
+    let mut env = env.borrow_mut();
+    let named = |prefix: &str, suffix: &str| Symbol::new(&format!("{}{}{}", prefix, name, suffix));
+    env.set(*name, Value::RecordType(rtype.clone()));
+    env.set(named("make-", ""), record::constructor(&rtype, fields)?);
+    env.set(named("", "?"), record::predicate(&rtype));
+    for (i, field) in fields.iter().enumerate() {
+        env.set(
+            named("", &format!("-{}", field)),
+            record::accessor(&rtype, i),
+        );
+        env.set(
+            named("set-", &format!("-{}!", field)),
+            record::modifier(&rtype, i),
+        );
+    }
+    Ok(Value::Nil)
+}
+
+/// `(define-record-type name (constructor field ...) predicate
+///    (field accessor [modifier]) ...)`
+fn define_record_type(list: &[Value], env: &mut Env) -> Result<Value, Exception> {
+    let invalid = || Exception::new("syntax-error", "Invalid define-record-type");
+    let symbols = |items: &[Value]| {
+        items
+            .iter()
+            .map(|v| match v {
+                Value::Symbol(s) => Ok(*s),
+                _ => Err(invalid()),
+            })
+            .collect::<Result<Vec<_>, _>>()
+    };
+    let (name, constructor, predicate, specs) = match list {
+        [_, Value::Symbol(name), Value::List(constructor), Value::Symbol(predicate), specs @ ..] => {
+            (*name, symbols(constructor)?, *predicate, specs)
+        }
+        _ => return Err(invalid()),
+    };
+    let specs = specs
+        .iter()
+        .map(|spec| match spec {
+            Value::List(l) if (2..=3).contains(&l.len()) => symbols(l),
+            _ => Err(invalid()),
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    let rtype = Rc::new(RecordType {
+        name,
+        fields: specs.iter().map(|spec| spec[0]).collect(),
+    });
+    let mut env = env.borrow_mut();
+    env.set(name, Value::RecordType(rtype.clone()));
+    if let Some((ctor, params)) = constructor.split_first() {
+        env.set(*ctor, record::constructor(&rtype, params)?);
+    }
+    env.set(predicate, record::predicate(&rtype));
+    for (i, spec) in specs.iter().enumerate() {
+        env.set(spec[1], record::accessor(&rtype, i));
+        if let Some(modifier) = spec.get(2) {
+            env.set(*modifier, record::modifier(&rtype, i));
+        }
+    }
+    Ok(Value::Nil)
+}
+
+/// `(define-syntax name (syntax-rules (literal ...) (pattern template) ...))`
+fn define_syntax(list: &[Value], env: &mut Env) -> Result<Value, Exception> {
+    if list.len() != 3 {
+        return Err(Exception::new(
+            "syntax-error",
+            "Invalid number of arguments for define-syntax",
+        ));
+    }
+    let symbol = match &list[1] {
+        Value::Symbol(s) => *s,
+        _ => return Err(Exception::new("syntax-error", "Invalid define-syntax")),
+    };
+    let rules = SyntaxRules::new(symbol.name(), &list[2])?;
+    env.borrow_mut()
+        .set(symbol, Value::SyntaxRules(Rc::new(rules)));
+    Ok(Value::Nil)
+}
+
+/// `macroexpand-1` expands the evaluated form once, `macroexpand` keeps going
+/// until it is no longer a macro call. Neither expands the subforms.
+fn macroexpand(list: &[Value], env: &mut Env, repeat: bool) -> Result<Tail, Exception> {
+    if list.len() != 2 {
+        return Err(Exception::new(
+            "syntax-error",
+            format!("Invalid number of arguments for {}", list[0]),
+        ));
+    }
+    let env = env.clone();
+    Ok(then(
+        Tail::Eval(list[1].clone(), env.clone()),
+        move |mut form| {
+            while let Some(expanded) = macros::expand_1(&form, &env)? {
+                form = expanded;
+                if !repeat {
+                    break;
+                }
+            }
+            Ok(Tail::Return(form))
+        },
+    ))
+}
+
+fn quote(list: &[Value]) -> Result<Value, Exception> {
+    if list.len() != 2 {
+        return Err(Exception::new(
+            "syntax-error",
+            "Invalid number of arguments for quote",
+        ));
+    }
+    Ok(list[1].clone())
+}
+
+fn quasiquote(list: &[Value], env: &mut Env) -> Result<Tail, Exception> {
+    if list.len() != 2 {
+        return Err(Exception::new(
+            "syntax-error",
+            "Invalid number of arguments for quasiquote",
+        ));
+    }
+    template(&list[1], 1, env)
+}
+
+/// Builds a quasiquote template, evaluating the parts unquoted at `depth` 1.
+fn template(node: &Value, depth: usize, env: &Env) -> Result<Tail, Exception> {
+    let list = match node {
+        Value::List(l) => l,
+        _ => return Ok(Tail::Return(node.clone())),
+    };
+    match list.as_slice() {
+        [Value::Symbol(known::UNQUOTE), x] => {
+            if depth == 1 {
+                Ok(Tail::Eval(x.clone(), env.clone()))
+            } else {
+                wrap(&list[0], template(x, depth - 1, env)?)
+            }
+        }
+        [Value::Symbol(known::UNQUOTE_SPLICING), x] => {
+            if depth == 1 {
+                Err(Exception::new(
+                    "syntax-error",
+                    "unquote-splicing must be used inside a list",
+                ))
+            } else {
+                wrap(&list[0], template(x, depth - 1, env)?)
+            }
+        }
+        [Value::Symbol(known::QUASIQUOTE), x] => wrap(&list[0], template(x, depth + 1, env)?),
+        _ => template_items(list.as_slice().into(), vec![], depth, env.clone()),
+    }
+}
+
+/// Goes on with `(head x)`, where `x` is the value of `tail`.
+fn wrap(head: &Value, tail: Tail) -> Result<Tail, Exception> {
+    let head = head.clone();
+    Ok(then(tail, move |x| {
+        Ok(Tail::Return(Value::List(vec![head.clone(), x].into())))
+    }))
+}
+
+/// Builds the items of a list template after the ones already `done`.
+fn template_items(
+    items: Rc<[Value]>,
+    mut done: Vec<Value>,
+    depth: usize,
+    env: Env,
+) -> Result<Tail, Exception> {
+    let item = match items.first() {
+        Some(item) => item,
+        None => return Ok(Tail::Return(Value::List(done.into()))),
+    };
+    let rest: Rc<[Value]> = items[1..].into();
+    let tail = match item {
+        Value::List(l) if depth == 1 && is_splice(l) => {
+            return Ok(then(Tail::Eval(l[1].clone(), env.clone()), move |val| {
+                let mut done = done.clone();
+                match pair::proper_list(&val) {
+                    Some(items) => done.extend(items.iter().cloned()),
+                    None => {
+                        return Err(Exception::new(
+                            "type-error",
+                            format!("Cannot splice {}, it is not a list", val),
+                        ))
+                    }
+                }
+                template_items(rest.clone(), done, depth, env.clone())
+            }));
+        }
+        Value::List(_) => template(item, depth, &env)?,
+        _ => {
+            done.push(item.clone());
+            return template_items(rest, done, depth, env);
+        }
+    };
+    Ok(then(tail, move |val| {
+        let mut done = done.clone();
+        done.push(val);
+        template_items(rest.clone(), done, depth, env.clone())
+    }))
+}
+
+fn is_splice(list: &[Value]) -> bool {
+    matches!(list, [Value::Symbol(known::UNQUOTE_SPLICING), _])
+}
+
+fn call(list: &[Value], env: &mut Env) -> Result<Tail, Exception> {
+    eval_all(list.into(), env, |mut values| {
+        let func = values.remove(0);
+        Ok(Tail::Call(func, values))
+    })
+}
+
+/// Calls `func` from Rust code, in a nested run of the evaluator.
+pub fn apply(func: &Value, args: &[Value]) -> Result<Value, Exception> {
+    run(Tail::Call(func.clone(), args.to_vec())).map(Value::single)
+}
+
+fn call_tail(func: &Value, args: &[Value]) -> Result<Tail, Exception> {
+    match func {
+        Value::Lambda(params, body_exprs, closure) => {
+            let new_env = Rc::new(RefCell::new(Environment::extend(closure.clone())));
+            bind(params, args, &new_env)?;
+            Ok(body(body_exprs, &new_env))
+        }
+        Value::Builtin(builtin) => match builtin.func {
+            Primitive::Value(f) => f(args).map(Tail::Return),
+            Primitive::Control(f) => f(args),
+        },
+        _ => Err(Exception::new(
+            "type-error",
+            format!("Not a lambda: {}", func),
+        )),
+    }
+}
+
+/// Binds arguments to parameters, a trailing `&rest name` collects whatever
+/// arguments are left into a list. The names after `&key` are bound to the
+/// arguments that follow the keywords with the same names, or to `nil`.
+fn bind(params: &[Symbol], args: &[Value], env: &Env) -> Result<(), Exception> {
+    let (params, keys) = match params.iter().position(|param| *param == known::KEY) {
+        Some(i) => (&params[..i], Some(&params[i + 1..])),
+        None => (params, None),
+    };
+    let (required, rest) = match params {
+        [required @ .., known::REST, rest] => (required, Some(rest)),
+        _ => (params, None),
+    };
+    let variadic = rest.is_some() || keys.is_some();
+    if args.len() < required.len() || (!variadic && args.len() > required.len()) {
+        return Err(Exception::new(
+            "arity-error",
+            format!(
+                "Expected {}{} arguments, got {}",
+                if variadic { "at least " } else { "" },
+                required.len(),
+                args.len()
+            ),
+        ));
+    }
+    for (param, arg) in required.iter().zip(args) {
+        env.borrow_mut().set(*param, arg.clone());
+    }
+    if let Some(rest) = rest {
+        let rest_args = args[required.len()..].to_vec();
+        env.borrow_mut().set(*rest, Value::List(rest_args.into()));
+    }
+    if let Some(keys) = keys {
+        bind_keys(keys, &args[required.len()..], env)?;
+    }
+    Ok(())
+}
+
+fn bind_keys(keys: &[Symbol], args: &[Value], env: &Env) -> Result<(), Exception> {
+    if !args.len().is_multiple_of(2) {
+        return Err(Exception::new(
+            "arity-error",
+            "Keyword arguments must come in pairs",
+        ));
+    }
+    for key in keys {
+        env.borrow_mut().set(*key, Value::Nil);
+    }
+    for pair in args.chunks(2) {
+        let key = match &pair[0] {
+            Value::Keyword(k) => keys.iter().find(|key| **key == k.name()),
+            _ => None,
+        };
+        match key {
+            Some(key) => env.borrow_mut().set(*key, pair[1].clone()),
+            None => {
+                return Err(Exception::new(
+                    "arity-error",
+                    format!("Unknown keyword argument {}", pair[0]),
+                ))
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Evaluates all but the last expression, which is left in tail position.
+fn body(exprs: &[Value], env: &Env) -> Tail {
+    match exprs {
+        [] => Tail::Return(Value::Nil),
+        [last] => Tail::Eval(last.clone(), env.clone()),
+        [first, rest @ ..] => {
+            let rest = rest.to_vec();
+            let body_env = env.clone();
+            then(Tail::Eval(first.clone(), env.clone()), move |_| {
+                Ok(body(&rest, &body_env))
+            })
+        }
+    }
+}
+
+fn cond(list: &[Value], env: &mut Env) -> Result<Tail, Exception> {
+    cond_from(list[1..].into(), 0, env.clone())
+}
+
+/// Tries the clauses of a `cond` from the i:th one on.
+fn cond_from(clauses: Rc<[Value]>, i: usize, env: Env) -> Result<Tail, Exception> {
+    let clause = match clauses.get(i) {
+        Some(Value::List(clause)) if !clause.is_empty() => clause,
+        Some(clause) => {
+            return Err(Exception::new(
+                "syntax-error",
+                format!("Invalid cond clause {}", clause),
+            ))
+        }
+        None => return Ok(Tail::Return(Value::Nil)),
+    };
+    if is_else(&clause[0]) {
+        if i != clauses.len() - 1 {
+            return Err(Exception::new(
+                "syntax-error",
+                "else must be the last clause of cond",
+            ));
+        }
+        return clause_body(None, &clause[1..], &env);
+    }
+    let exprs = clause[1..].to_vec();
+    Ok(then(
+        Tail::Eval(clause[0].clone(), env.clone()),
+        move |test| {
+            if test.is_truthy() {
+                clause_body(Some(test), &exprs, &env)
+            } else {
+                cond_from(clauses.clone(), i + 1, env.clone())
+            }
+        },
+    ))
+}
+
+/// `(case key ((datum ...) body ...) ... (else body ...))` compares the key
+/// against the unevaluated datums of each clause.
+fn case(list: &[Value], env: &mut Env) -> Result<Tail, Exception> {
+    if list.len() < 2 {
+        return Err(Exception::new(
+            "syntax-error",
+            "Invalid number of arguments for case",
+        ));
+    }
+    let clauses = list[2..].to_vec();
+    let env = env.clone();
+    Ok(then(Tail::Eval(list[1].clone(), env.clone()), move |key| {
+        for (i, node) in clauses.iter().enumerate() {
+            let clause = match node {
+                Value::List(clause) if !clause.is_empty() => clause,
+                _ => {
+                    return Err(Exception::new(
+                        "syntax-error",
+                        format!("Invalid case clause {}", node),
+                    ))
+                }
+            };
+            match &clause[0] {
+                head if is_else(head) => {
+                    if i != clauses.len() - 1 {
+                        return Err(Exception::new(
+                            "syntax-error",
+                            "else must be the last clause of case",
+                        ));
+                    }
+                    return clause_body(Some(key), &clause[1..], &env);
+                }
+                Value::List(datums) => {
+                    if datums.contains(&key) {
+                        return clause_body(Some(key), &clause[1..], &env);
+                    }
+                }
+                _ => {
+                    return Err(Exception::new(
+                        "syntax-error",
+                        format!("Invalid case clause {}", node),
+                    ))
+                }
+            }
+        }
+        Ok(Tail::Return(Value::Nil))
+    }))
+}
+
+/// `(match expr (pattern body ...) ...)` evaluates the body of the first clause
+/// whose pattern matches the value of `expr`, with the pattern's variables
+/// bound. A clause `(pattern :when guard body ...)` only matches if `guard`
+/// is also true with them bound. See `pattern::matches` for the patterns.
+fn _match(list: &[Value], env: &mut Env) -> Result<Tail, Exception> {
+    if list.len() < 2 {
+        return Err(Exception::new(
+            "syntax-error",
+            "Invalid number of arguments for match",
+        ));
+    }
+    let mut clauses = vec![];
+    for clause in &list[2..] {
+        match clause {
+            Value::List(c) if !c.is_empty() => clauses.push(c.clone()),
+            _ => {
+                return Err(Exception::new(
+                    "syntax-error",
+                    format!("Invalid match clause {}", clause),
+                ))
+            }
+        }
+    }
+    let clauses: Rc<[List]> = clauses.into();
+    let env = env.clone();
+    Ok(then(Tail::Eval(list[1].clone(), env.clone()), move |val| {
+        match_from(clauses.clone(), val, env.clone())
+    }))
+}
+
+/// Tries the clauses in order, the guard of a clause that matches decides
+/// whether to go on with the rest.
+fn match_from(clauses: Rc<[List]>, val: Value, env: Env) -> Result<Tail, Exception> {
+    for (i, clause) in clauses.iter().enumerate() {
+        let mut bound = vec![];
+        if !pattern::matches(&clause[0], &val, &env, &mut bound)? {
+            continue;
+        }
+        let scope = Rc::new(RefCell::new(Environment::extend(env.clone())));
+        for (name, v) in bound {
+            scope.borrow_mut().set(name, v);
+        }
+        return match clause.get(1) {
+            Some(Value::Keyword(k)) if k.name() == "when" => {
+                let guard = clause.get(2).cloned().ok_or_else(|| {
+                    Exception::new("syntax-error", ":when must be followed by a guard")
+                })?;
+                let exprs = clause.tail(3);
+                let rest: Rc<[List]> = clauses[i + 1..].into();
+                let env = env.clone();
+                Ok(then(Tail::Eval(guard, scope.clone()), move |ok| {
+                    if ok.is_truthy() {
+                        Ok(body(&exprs, &scope))
+                    } else {
+                        match_from(rest.clone(), val.clone(), env.clone())
+                    }
+                }))
+            }
+            _ => Ok(body(&clause[1..], &scope)),
+        };
+    }
+    Err(Exception::with_data(
+        "match-error",
+        format!("No clause matches {}", val),
+        val,
+    ))
+}
+
+fn is_else(node: &Value) -> bool {
+    matches!(node, Value::Symbol(known::ELSE))
+}
+
+/// The body of a selected `cond` or `case` clause. `(=> f)` calls `f` with the
+/// tested value and an empty body returns the tested value itself.
+fn clause_body(test: Option<Value>, exprs: &[Value], env: &Env) -> Result<Tail, Exception> {
+    match (test, exprs) {
+        (Some(test), [Value::Symbol(known::ARROW), f]) => {
+            Ok(then(Tail::Eval(f.clone(), env.clone()), move |f| {
+                Ok(Tail::Call(f, vec![test.clone()]))
+            }))
+        }
+        (Some(test), []) => Ok(Tail::Return(test)),
+        (_, exprs) => Ok(body(exprs, env)),
+    }
+}
+
+/// `(if test then)` or `(if test then else)`, a missing else branch is `nil`.
+fn _if(list: &[Value], env: &mut Env) -> Result<Tail, Exception> {
+    if list.len() != 3 && list.len() != 4 {
+        return Err(Exception::new(
+            "syntax-error",
+            "Invalid number of arguments for if",
+        ));
+    }
+    let consequent = list[2].clone();
+    let alternative = list.get(3).cloned();
+    let env = env.clone();
+    Ok(then(
+        Tail::Eval(list[1].clone(), env.clone()),
+        move |test| {
+            if test.is_truthy() {
+                Ok(Tail::Eval(consequent.clone(), env.clone()))
+            } else if let Some(alternative) = &alternative {
+                Ok(Tail::Eval(alternative.clone(), env.clone()))
+            } else {
+                Ok(Tail::Return(Value::Nil))
+            }
+        },
+    ))
+}
+
+/// `when` runs its body if the test is true, `unless` if it is false.
+fn when(list: &[Value], env: &mut Env, expected: bool) -> Result<Tail, Exception> {
+    if list.len() < 2 {
+        return Err(Exception::new(
+            "syntax-error",
+            format!("Invalid number of arguments for {}", list[0]),
+        ));
+    }
+    let exprs = list[2..].to_vec();
+    let env = env.clone();
+    Ok(then(
+        Tail::Eval(list[1].clone(), env.clone()),
+        move |test| {
+            if test.is_truthy() == expected {
+                Ok(body(&exprs, &env))
+            } else {
+                Ok(Tail::Return(Value::Nil))
+            }
+        },
+    ))
+}
+
+/// `and` returns the first false value, or the last value if all of them are
+/// true. `or` returns the first true value, or the last value if none of them
+/// are true.
+fn short_circuit(exprs: Rc<[Value]>, env: Env, stop_at: bool) -> Result<Tail, Exception> {
+    match exprs.len() {
+        0 => Ok(Tail::Return(Value::from(!stop_at))),
+        1 => Ok(Tail::Eval(exprs[0].clone(), env)),
+        _ => Ok(then(
+            Tail::Eval(exprs[0].clone(), env.clone()),
+            move |res| {
+                if res.is_truthy() == stop_at {
+                    Ok(Tail::Return(res))
+                } else {
+                    short_circuit(exprs[1..].into(), env.clone(), stop_at)
+                }
+            },
+        )),
+    }
+}
+
+/// `(try body ... (catch e handler ...) (finally cleanup ...))` evaluates the
+/// body, and if it raises, binds the raised value to `e` and runs the handler.
+/// The cleanup always runs last, an error it raises replaces the result.
+fn try_catch(list: &[Value], env: &mut Env) -> Result<Tail, Exception> {
+    let mut exprs = &list[1..];
+    let mut finally = None;
+    let mut catch = None;
+    if let Some((Value::List(clause), init)) = exprs.split_last() {
+        if is_clause(clause, known::FINALLY) {
+            finally = Some(&clause[1..]);
+            exprs = init;
+        }
+    }
+    if let Some((Value::List(clause), init)) = exprs.split_last() {
+        if is_clause(clause, known::CATCH) {
+            match clause.get(1) {
+                Some(Value::Symbol(name)) => catch = Some((name, &clause[2..])),
+                _ => {
+                    return Err(Exception::new(
+                        "syntax-error",
+                        "catch expects a variable name",
+                    ))
+                }
+            }
+            exprs = init;
+        }
+    }
+    if exprs.iter().any(
+        |expr| matches!(expr, Value::List(l) if is_clause(l, known::CATCH) || is_clause(l, known::FINALLY)),
+    ) {
+        return Err(Exception::new(
+            "syntax-error",
+            "catch and finally must be the last clauses of try",
+        ));
+    }
+    let mut tail = body(exprs, env);
+    if let Some((name, handler)) = catch {
+        let outer = conditions::current();
+        conditions::bind_catch();
+        tail = push(
+            Frame::Catch(*name, handler.into(), env.clone()),
+            push(Frame::Dynamic(outer), tail),
+        );
+    }
+    if let Some(cleanup) = finally {
+        tail = push(Frame::Finally(cleanup.into(), env.clone()), tail);
+    }
+    Ok(tail)
+}
+
+/// `(handler-bind ((kind handler) ...) body ...)` calls the handler for a
+/// matching condition at the point where it is signaled, before anything is
+/// unwound.
+fn handler_bind(list: &[Value], env: &mut Env) -> Result<Tail, Exception> {
+    let clauses = match list.get(1) {
+        Some(Value::List(clauses)) => clauses,
+        _ => {
+            return Err(Exception::new(
+                "syntax-error",
+                "handler-bind expects a list of handlers",
+            ))
+        }
+    };
+    let mut kinds = vec![];
+    let mut handlers = vec![];
+    for clause in clauses {
+        match clause {
+            Value::List(c) => match c.as_slice() {
+                [Value::Symbol(kind), handler] => {
+                    kinds.push(*kind);
+                    handlers.push(handler.clone());
+                }
+                _ => {
+                    return Err(Exception::new(
+                        "syntax-error",
+                        format!("Invalid handler {}", clause),
+                    ))
+                }
+            },
+            _ => {
+                return Err(Exception::new(
+                    "syntax-error",
+                    format!("Invalid handler {}", clause),
+                ))
+            }
+        }
+    }
+    let exprs = list[2..].to_vec();
+    let body_env = env.clone();
+    eval_all(handlers.into(), env, move |handlers| {
+        let outer = conditions::current();
+        conditions::bind_handlers(kinds.iter().cloned().zip(handlers).collect());
+        Ok(push(Frame::Dynamic(outer), body(&exprs, &body_env)))
+    })
+}
+
+/// `(restart-case expr (name (param ...) body ...) ...)` evaluates `expr` with
+/// the restarts established. Invoking one unwinds back here and the value of its
+/// body becomes the value of the form.
+fn restart_case(list: &[Value], env: &mut Env) -> Result<Tail, Exception> {
+    let expr = match list.get(1) {
+        Some(expr) => expr,
+        None => {
+            return Err(Exception::new(
+                "syntax-error",
+                "Invalid number of arguments for restart-case",
+            ))
+        }
+    };
+    let mut names = vec![];
+    let mut restarts = vec![];
+    for clause in &list[2..] {
+        match clause {
+            Value::List(c) if matches!(c.first(), Some(Value::Symbol(_))) => {
+                // Restarts are found by name, not in scope, so a name from a
+                // macro template means its original.
+                if let Value::Symbol(name) = c[0] {
+                    names.push(name.original());
+                }
+                // Each restart has the same shape as a named `fn` form.
+                restarts.push(_fn(c, env)?);
+            }
+            _ => {
+                return Err(Exception::new(
+                    "syntax-error",
+                    format!("Invalid restart {}", clause),
+                ))
+            }
+        }
+    }
+    let outer = conditions::current();
+    let first = conditions::bind_restarts(&names);
+    Ok(push(
+        Frame::Restarts(first, restarts.into()),
+        push(Frame::Dynamic(outer), Tail::Eval(expr.clone(), env.clone())),
+    ))
+}
+
+/// `(shift k body ...)` binds `k` to the rest of the computation up to the
+/// innermost `(reset ...)` and evaluates the body in place of that `reset`.
+fn shift_form(list: &[Value], env: &mut Env) -> Result<Tail, Exception> {
+    if list.len() < 3 {
+        return Err(Exception::new(
+            "syntax-error",
+            "Invalid number of arguments for shift",
+        ));
+    }
+    let func = match &list[1] {
+        Value::Symbol(k) => Value::Lambda(vec![*k], list[2..].to_vec(), env.clone()),
+        _ => return Err(Exception::new("syntax-error", "Invalid shift")),
+    };
+    Ok(Tail::Shift(func))
+}
+
+/// Evaluates `tail` as one iteration of a loop, then goes on with `next`.
+fn iteration(tail: Tail, next: impl Fn(Value) -> Result<Tail, Exception> + 'static) -> Tail {
+    push(Frame::Loop(Rc::new(next)), tail)
+}
+
+/// `(while test body ...)` evaluates the body as long as `test` is true.
+fn _while(list: &[Value], env: &mut Env) -> Result<Tail, Exception> {
+    if list.len() < 2 {
+        return Err(Exception::new(
+            "syntax-error",
+            "Invalid number of arguments for while",
+        ));
+    }
+    while_from(list[1].clone(), list[2..].into(), env.clone())
+}
+
+fn while_from(test: Value, exprs: Rc<[Value]>, env: Env) -> Result<Tail, Exception> {
+    Ok(then(Tail::Eval(test.clone(), env.clone()), move |val| {
+        if !val.is_truthy() {
+            return Ok(Tail::Return(Value::Nil));
+        }
+        let (test, exprs, env) = (test.clone(), exprs.clone(), env.clone());
+        Ok(iteration(body(&exprs, &env), move |_| {
+            while_from(test.clone(), exprs.clone(), env.clone())
+        }))
+    }))
+}
+
+/// `(loop body ...)` evaluates the body until `break` leaves it.
+fn _loop(exprs: Rc<[Value]>, env: Env) -> Tail {
+    iteration(body(&exprs, &env), move |_| {
+        Ok(_loop(exprs.clone(), env.clone()))
+    })
+}
+
+/// The `(var init [result])` of `dotimes` and `dolist`.
+fn loop_spec(list: &[Value]) -> Result<(Symbol, Value, Option<Value>), Exception> {
+    let spec = match list.get(1) {
+        Some(Value::List(spec)) => spec,
+        _ => {
+            return Err(Exception::new(
+                "syntax-error",
+                format!("Invalid {}", list[0]),
+            ))
+        }
+    };
+    match spec.as_slice() {
+        [Value::Symbol(var), init] => Ok((*var, init.clone(), None)),
+        [Value::Symbol(var), init, result] => Ok((*var, init.clone(), Some(result.clone()))),
+        _ => Err(Exception::new(
+            "syntax-error",
+            format!("Invalid {} binding", list[0]),
+        )),
+    }
+}
+
+/// Evaluates the body in a new scope with `var` bound to each of `vals`, then
+/// `result` with `var` bound to `last`.
+fn each_from(
+    var: Symbol,
+    vals: Rc<dyn Fn(usize) -> Option<Value>>,
+    i: usize,
+    last: Value,
+    result: Option<Value>,
+    exprs: Rc<[Value]>,
+    env: Env,
+) -> Tail {
+    let scope = Rc::new(RefCell::new(Environment::extend(env.clone())));
+    let val = match vals(i) {
+        Some(val) => val,
+        None => {
+            scope.borrow_mut().set(var, last);
+            return match result {
+                Some(result) => Tail::Eval(result, scope),
+                None => Tail::Return(Value::Nil),
+            };
+        }
+    };
+    scope.borrow_mut().set(var, val);
+    iteration(body(&exprs, &scope), move |_| {
+        Ok(each_from(
+            var,
+            vals.clone(),
+            i + 1,
+            last.clone(),
+            result.clone(),
+            exprs.clone(),
+            env.clone(),
+        ))
+    })
+}
+
+/// `(dotimes (var count [result]) body ...)` evaluates the body with `var`
+/// bound to 0 up to `count` - 1.
+fn dotimes(list: &[Value], env: &mut Env) -> Result<Tail, Exception> {
+    let (var, count, result) = loop_spec(list)?;
+    let exprs: Rc<[Value]> = list[2..].into();
+    let env = env.clone();
+    Ok(then(Tail::Eval(count, env.clone()), move |count| {
+        let n = match count {
+            Value::Number(n) => n,
+            _ => {
+                return Err(Exception::new(
+                    "type-error",
+                    format!("dotimes expects a count, got {}", count),
+                ))
+            }
+        };
+        let vals = move |i: usize| (i < n as usize).then(|| Value::Number(i as f64));
+        Ok(each_from(
+            var,
+            Rc::new(vals),
+            0,
+            Value::Number(n.max(0.0).floor()),
+            result.clone(),
+            exprs.clone(),
+            env.clone(),
+        ))
+    }))
+}
+
+/// `(dolist (var list [result]) body ...)` evaluates the body with `var` bound
+/// to each element of the list.
+fn dolist(list: &[Value], env: &mut Env) -> Result<Tail, Exception> {
+    let (var, items, result) = loop_spec(list)?;
+    let exprs: Rc<[Value]> = list[2..].into();
+    let env = env.clone();
+    Ok(then(Tail::Eval(items, env.clone()), move |items| {
+        let items = match pair::proper_list(&items) {
+            Some(l) => l,
+            None if items == Value::Nil => List::new(),
+            None => {
+                return Err(Exception::new(
+                    "type-error",
+                    format!("dolist expects a list, got {}", items),
+                ))
+            }
+        };
+        Ok(each_from(
+            var,
+            Rc::new(move |i| items.get(i).cloned()),
+            0,
+            Value::Nil,
+            result.clone(),
+            exprs.clone(),
+            env.clone(),
+        ))
+    }))
+}
+
+/// `(delay expr)` makes a promise that evaluates `expr` when it is forced.
+fn delay(list: &[Value], env: &mut Env) -> Result<Tail, Exception> {
+    if list.len() != 2 {
+        return Err(Exception::new(
+            "syntax-error",
+            "Invalid number of arguments for delay",
+        ));
+    }
+    Ok(Tail::Return(delayed(&list[1], env)))
+}
+
+fn delayed(expr: &Value, env: &Env) -> Value {
+    let (expr, env) = (expr.clone(), env.clone());
+    Value::Promise(Rc::new(Promise::delay(move || {
+        Ok(Tail::Eval(expr.clone(), env.clone()))
+    })))
+}
+
+/// `(stream-cons head tail)` makes a stream, the list of `head` and a promise
+/// of the `tail` stream.
+fn stream_cons(list: &[Value], env: &mut Env) -> Result<Tail, Exception> {
+    if list.len() != 3 {
+        return Err(Exception::new(
+            "syntax-error",
+            "Invalid number of arguments for stream-cons",
+        ));
+    }
+    let tail = delayed(&list[2], env);
+    Ok(then(
+        Tail::Eval(list[1].clone(), env.clone()),
+        move |head| Ok(Tail::Return(Value::List(vec![head, tail.clone()].into()))),
+    ))
+}
+
+fn is_clause(clause: &[Value], name: Symbol) -> bool {
+    matches!(clause.first(), Some(Value::Symbol(s)) if *s == name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_add() {
+        let mut env = Rc::new(RefCell::new(Environment::new()));
+        let result = evaluate("(+ 1 1)", &mut env).unwrap();
+        assert_eq!(result, Value::Number(2.0));
+    }
+
+    #[test]
+    fn test_sub() {
+        let mut env = Rc::new(RefCell::new(Environment::new()));
+        let result = evaluate("(- 2 1)", &mut env).unwrap();
+        assert_eq!(result, Value::Number(1.0));
+    }
+
+    #[test]
+    fn test_mul() {
+        let mut env = Rc::new(RefCell::new(Environment::new()));
+        let result = evaluate("(* 2.5 2)", &mut env).unwrap();
+        assert_eq!(result, Value::Number(5.0));
+    }
+
+    #[test]
+    fn test_div() {
+        let mut env = Rc::new(RefCell::new(Environment::new()));
+        let result = evaluate("(/ 5 2)", &mut env).unwrap();
+        assert_eq!(result, Value::Number(2.5));
+    }
+
+    #[test]
+    fn test_gt() {
+        let mut env = Rc::new(RefCell::new(Environment::new()));
+        let result = evaluate("(gt 5 2)", &mut env).unwrap();
+        assert_eq!(result, Value::Number(1.0));
+    }
+
+    #[test]
+    fn test_gte() {
+        let mut env = Rc::new(RefCell::new(Environment::new()));
+        let result = evaluate("(gte 5 5)", &mut env).unwrap();
+        assert_eq!(result, Value::Number(1.0));
+    }
+
+    #[test]
+    fn test_gte_neg() {
+        let mut env = Rc::new(RefCell::new(Environment::new()));
+        let result = evaluate("(gte 4 5)", &mut env).unwrap();
+        assert_eq!(result, Value::Number(0.0));
+    }
+
+    #[test]
+    fn test_lt() {
+        let mut env = Rc::new(RefCell::new(Environment::new()));
+        let result = evaluate("(lt 4 5)", &mut env).unwrap();
+        assert_eq!(result, Value::Number(1.0));
+    }
+
+    #[test]
+    fn test_eq() {
+        let mut env = Rc::new(RefCell::new(Environment::new()));
+        let result = evaluate("(eq 4 4)", &mut env).unwrap();
+        assert_eq!(result, Value::Number(1.0));
+    }
+
+    #[test]
+    fn test_eq_neg() {
+        let mut env = Rc::new(RefCell::new(Environment::new()));
+        let result = evaluate("(eq 4 5)", &mut env).unwrap();
+        assert_eq!(result, Value::Number(0.0));
+    }
+
+    #[test]
+    fn test_not() {
+        let mut env = Rc::new(RefCell::new(Environment::new()));
+        let result = evaluate("(not 1)", &mut env).unwrap();
+        assert_eq!(result, Value::Number(0.0));
+    }
+
+    #[test]
+    fn test_not2() {
+        let mut env = Rc::new(RefCell::new(Environment::new()));
+        let result = evaluate("(not 0)", &mut env).unwrap();
+        assert_eq!(result, Value::Number(1.0));
+    }
+
+    #[test]
+    fn test_not_eq() {
+        let mut env = Rc::new(RefCell::new(Environment::new()));
+        let result = evaluate("(not (eq 1 0))", &mut env).unwrap();
+        assert_eq!(result, Value::Number(1.0));
+    }
+
+    #[test]
+    fn test_not_gt() {
+        let mut env = Rc::new(RefCell::new(Environment::new()));
+        let result = evaluate("(not (gt 0 1))", &mut env).unwrap();
+        assert_eq!(result, Value::Number(1.0));
+    }
+
+    #[test]
+    fn test_simple_program() {
+        let mut env= Rc::new(RefCell::new(Environment::new()));
+        let source = "(define b 10)
+                      (define h 14)
+                      (/ (* b h) 2)";
+        let res = evaluate(source, &mut env).unwrap();
+        assert_eq!(res, Value::List(vec![Value::Number(70.0)].into()));
+    }
+
+    #[test]
+    fn test_conditional() {
+        let mut env= Rc::new(RefCell::new(Environment::new()));
+        let source = "(define factorial (fn (n) (cond ((lt n 1) 1) (else (* n (factorial (- n 1)))))))(factorial 5)";
+        let res = evaluate(source, &mut env).unwrap();
+        assert_eq!(res, Value::List(vec![Value::Number(120.0)].into()));
+    }
+
+    #[test]
+    fn test_immediately_invoked_lambda() {
+        let mut env = Rc::new(RefCell::new(Environment::new()));
+        let res = evaluate("((fn (x) (* x x)) 5)", &mut env).unwrap();
+        assert_eq!(res, Value::Number(25.0));
+    }
+
+    #[test]
+    fn test_returned_closure() {
+        let mut env = Rc::new(RefCell::new(Environment::new()));
+        let source = "(define adder (fn (n) (fn (x) (+ x n))))((adder 2) 3)";
+        let res = evaluate(source, &mut env).unwrap();
+        assert_eq!(res, Value::List(vec![Value::Number(5.0)].into()));
+    }
+
+    #[test]
+    fn test_call_non_function() {
+        let mut env = Rc::new(RefCell::new(Environment::new()));
+        assert!(evaluate("(1 2 3)", &mut env).is_err());
+    }
+
+    #[test]
+    fn test_call_wrong_arity() {
+        let mut env = Rc::new(RefCell::new(Environment::new()));
+        assert!(evaluate("((fn (x y) (+ x y)) 1)", &mut env).is_err());
+    }
+
+    #[test]
+    fn test_if() {
+        let mut env = Rc::new(RefCell::new(Environment::new()));
+        assert_eq!(
+            evaluate("(if (gt 2 1) 1 2)", &mut env).unwrap(),
+            Value::Number(1.0)
+        );
+        assert_eq!(
+            evaluate("(if (gt 1 2) 1 2)", &mut env).unwrap(),
+            Value::Number(2.0)
+        );
+        assert_eq!(evaluate("(if (gt 1 2) 1)", &mut env).unwrap(), Value::Nil);
+    }
+
+    #[test]
+    fn test_when_unless() {
+        let mut env = Rc::new(RefCell::new(Environment::new()));
+        let source = "(when (gt 2 1) (define x 1) (+ x 1))";
+        assert_eq!(evaluate(source, &mut env).unwrap(), Value::Number(2.0));
+        assert_eq!(evaluate("(when 0 (+ 1 1))", &mut env).unwrap(), Value::Nil);
+        assert_eq!(
+            evaluate("(unless 0 (+ 1 1))", &mut env).unwrap(),
+            Value::Number(2.0)
+        );
+        assert_eq!(
+            evaluate("(unless 1 (+ 1 1))", &mut env).unwrap(),
+            Value::Nil
+        );
+    }
+
+    #[test]
+    fn test_and_or() {
+        let mut env = Rc::new(RefCell::new(Environment::new()));
+        assert_eq!(
+            evaluate("(and 1 2 3)", &mut env).unwrap(),
+            Value::Number(3.0)
+        );
+        assert_eq!(
+            evaluate("(and 1 0 3)", &mut env).unwrap(),
+            Value::Number(0.0)
+        );
+        assert_eq!(evaluate("(and)", &mut env).unwrap(), Value::Number(1.0));
+        assert_eq!(
+            evaluate("(or 0 2 3)", &mut env).unwrap(),
+            Value::Number(2.0)
+        );
+        assert_eq!(evaluate("(or 0 0)", &mut env).unwrap(), Value::Number(0.0));
+        assert_eq!(evaluate("(or)", &mut env).unwrap(), Value::Number(0.0));
+    }
+
+    #[test]
+    fn test_short_circuit() {
+        let mut env = Rc::new(RefCell::new(Environment::new()));
+        assert_eq!(
+            evaluate("(and 0 undefined)", &mut env).unwrap(),
+            Value::Number(0.0)
+        );
+        assert_eq!(
+            evaluate("(or 1 undefined)", &mut env).unwrap(),
+            Value::Number(1.0)
+        );
+        assert_eq!(
+            evaluate("(if 1 1 undefined)", &mut env).unwrap(),
+            Value::Number(1.0)
+        );
+    }
+
+    #[test]
+    fn test_tail_calls() {
+        let mut env = Rc::new(RefCell::new(Environment::new()));
+        let source = "(define count (fn (n acc)
+                        (if (eq n 0)
+                          acc
+                          (and 1 (or 0 (when 1 (count (- n 1) (+ acc 1))))))))
+                      (count 20000 0)";
+        let res = evaluate(source, &mut env).unwrap();
+        assert_eq!(res, Value::List(vec![Value::Number(20000.0)].into()));
+    }
+
+    #[test]
+    fn test_cond_tests_every_clause() {
+        let mut env = Rc::new(RefCell::new(Environment::new()));
+        let res = evaluate("(cond ((gt 1 2) 1) ((gt 1 3) 2))", &mut env).unwrap();
+        assert_eq!(res, Value::Nil);
+    }
+
+    #[test]
+    fn test_empty_cond() {
+        let mut env = Rc::new(RefCell::new(Environment::new()));
+        let res = evaluate("(cond)", &mut env).unwrap();
+        assert_eq!(res, Value::Nil);
+    }
+
+    #[test]
+    fn test_cond_else_must_be_last() {
+        let mut env = Rc::new(RefCell::new(Environment::new()));
+        assert!(evaluate("(cond (else 1) ((gt 1 2) 2))", &mut env).is_err());
+    }
+
+    #[test]
+    fn test_cond_invalid_clause() {
+        let mut env = Rc::new(RefCell::new(Environment::new()));
+        match evaluate("(cond 1 (else 2))", &mut env) {
+            Err(Exception::Raise(Value::Error(e))) => assert_eq!(e.kind, "syntax-error"),
+            other => panic!("expected a syntax error, got {:?}", other),
+        }
+        match evaluate("(let (1) 3)", &mut env) {
+            Err(Exception::Raise(Value::Error(e))) => assert_eq!(e.kind, "syntax-error"),
+            other => panic!("expected a syntax error, got {:?}", other),
+        }
+    }
 
-use crate::{
-    environment::{Env, Environment},
-    parser::parse,
-    value::Value,
-};
+    #[test]
+    fn test_cond_multiple_expressions() {
+        let mut env = Rc::new(RefCell::new(Environment::new()));
+        let res = evaluate("(cond ((gt 2 1) (define x 2) (* x 3)))", &mut env).unwrap();
+        assert_eq!(res, Value::Number(6.0));
+    }
 
-pub fn evaluate(source: &str, env: &mut Env) -> Result<Value, String> {
-    if let Ok(parsed_program) = parse(source) {
-        value(&parsed_program, env)
-    } else {
-        Err("Something hapened?".to_string())
+    #[test]
+    fn test_cond_test_only_clause() {
+        let mut env = Rc::new(RefCell::new(Environment::new()));
+        let res = evaluate("(cond ((gt 1 2)) ((+ 2 3)))", &mut env).unwrap();
+        assert_eq!(res, Value::Number(5.0));
     }
-}
 
-fn value(node: &Value, env: &mut Env) -> Result<Value, String> {
-    match node {
-        Value::Symbol(s) => symbol(s, env),
-        Value::Number(n) => Ok(Value::Number(*n)),
-        Value::List(l) => list(l, env),
-        _ => Ok(Value::Nil),
+    #[test]
+    fn test_cond_arrow() {
+        let mut env = Rc::new(RefCell::new(Environment::new()));
+        let res = evaluate("(cond ((+ 2 3) => (fn (x) (* x x))))", &mut env).unwrap();
+        assert_eq!(res, Value::Number(25.0));
     }
-}
 
-fn symbol(s: &str, env: &mut Env) -> Result<Value, String> {
-    if let Some(val) = env.borrow_mut().get(s) {
-        Ok(val.clone())
-    } else {
-        Err(format!("Unbound symbol {}", s))
+    #[test]
+    fn test_case() {
+        let mut env = Rc::new(RefCell::new(Environment::new()));
+        let source = "(define size (fn (n) (case n ((0) 10) ((1 2 3) 20) (else 30))))
+                      (map size (list 0 2 7))";
+        let res = evaluate(source, &mut env).unwrap();
+        assert_eq!(
+            res,
+            Value::List(
+                vec![Value::List(
+                    vec![
+                        Value::Number(10.0),
+                        Value::Number(20.0),
+                        Value::Number(30.0),
+                    ]
+                    .into()
+                )]
+                .into()
+            )
+        );
     }
-}
 
-fn list(list: &Vec<Value>, env: &mut Env) -> Result<Value, String> {
-    let head = &list.first().unwrap();
-    match head {
-        Value::Symbol(s) => match s.as_str() {
-            "not" => not(&list, env),
-            "+" | "-" | "*" | "/" | "gt" | "gte" | "lt" | "lte" | "eq" => bin_op(&list, env),
-            "let" => _let(&list, env),
-            "fn" => _fn(&list),
-            "cond" => cond(&list, env),
-            _ => call(&s, &list, env),
-        },
-        _ => {
-            let mut new_list = vec![];
-            for node in list {
-                let res = value(node, env)?;
-                match res {
-                    Value::Nil => {}
-                    _ => new_list.push(res),
-                }
-            }
-            Ok(Value::List(new_list))
-        }
+    #[test]
+    fn test_case_arrow() {
+        let mut env = Rc::new(RefCell::new(Environment::new()));
+        let res = evaluate("(case (+ 1 1) ((2) => (fn (x) (* x 10))))", &mut env).unwrap();
+        assert_eq!(res, Value::Number(20.0));
     }
-}
 
-fn bin_op(list: &Vec<Value>, env: &mut Env) -> Result<Value, String> {
-    if list.len() < 3 {
-        return Err("Insufficient number of arguments".to_string());
+    #[test]
+    fn test_let() {
+        let mut env = Rc::new(RefCell::new(Environment::new()));
+        let source = "(define x 1)
+                      (let ((x 10) (y x)) (define z 5) (+ x y z))
+                      x";
+        let res = evaluate(source, &mut env).unwrap();
+        assert_eq!(
+            res,
+            Value::List(vec![Value::Number(16.0), Value::Number(1.0)].into())
+        );
     }
-    let op = &list.first().unwrap();
-    let operands = list[1..]
-        .iter()
-        .map(|node| value(node, env))
-        .collect::<Result<Vec<Value>, String>>()?;
-    let start = match &operands.first() {
-        Some(Value::Number(n)) => *n,
-        _ => return Err("Operands must be numbers".to_string()),
-    };
-    let final_result = operands[1..].iter().try_fold(start, |acc, node| {
-        if let Value::Number(n) = node {
-            match op {
-                Value::Symbol(s) => match s.as_str() {
-                    "+" => Ok(acc + n),
-                    "-" => Ok(acc - n),
-                    "*" => Ok(acc * n),
-                    "/" => Ok(acc / n),
-                    "gt" => Ok(if acc > *n { 1.0 } else { 0.0 }),
-                    "gte" => Ok(if acc >= *n { 1.0 } else { 0.0 }),
-                    "lt" => Ok(if acc < *n { 1.0 } else { 0.0 }),
-                    "lte" => Ok(if acc <= *n { 1.0 } else { 0.0 }),
-                    "eq" => Ok(if acc == *n { 1.0 } else { 0.0 }),
-                    _ => return Err(format!("Invalid operator {}", s)),
-                },
-                _ => return Err(format!("Operator must be a symbol")),
-            }
-        } else {
-            return Err("Operands must be numbers".to_string());
-        }
-    })?;
-    Ok(Value::Number(final_result))
-}
 
-fn not(list: &Vec<Value>, env: &mut Env) -> Result<Value, String> {
-    if list.len() != 2 {
-        return Err("Incorrect number of arguments".to_string());
+    #[test]
+    fn test_let_is_scoped() {
+        let mut env = Rc::new(RefCell::new(Environment::new()));
+        assert!(evaluate("(let ((x 1)) x) x", &mut env).is_err());
     }
-    if let Ok(Value::Number(n)) = value(&list[1], env) {
-        Ok(Value::Number(if n == 0.0 { 1.0 } else { 0.0 }))
-    } else {
-        Err("Invalid argument".to_string())
+
+    #[test]
+    fn test_let_star() {
+        let mut env = Rc::new(RefCell::new(Environment::new()));
+        let res = evaluate("(let* ((x 1) (y (+ x 1)) (x (* y 10))) (+ x y))", &mut env).unwrap();
+        assert_eq!(res, Value::Number(22.0));
     }
-}
 
-fn _let(list: &Vec<Value>, env: &mut Env) -> Result<Value, String> {
-    if list.len() != 3 {
-        return Err("Invalid number of arguments for let".to_string());
+    #[test]
+    fn test_letrec() {
+        let mut env = Rc::new(RefCell::new(Environment::new()));
+        let source = "(letrec ((even (fn (n) (if (eq n 0) 1 (odd (- n 1)))))
+                               (odd (fn (n) (if (eq n 0) 0 (even (- n 1))))))
+                        (list (even 10) (odd 7) (even 7)))";
+        let res = evaluate(source, &mut env).unwrap();
+        assert_eq!(
+            res,
+            Value::List(vec![Value::Number(1.0), Value::Number(1.0), Value::Number(0.0)].into())
+        );
     }
-    let symbol = match &list[1] {
-        Value::Symbol(s) => s.clone(),
-        _ => return Err("Invalid let".to_string()),
-    };
-    let val = value(&list[2], env)?;
-    env.borrow_mut().set(&symbol, val);
-    Ok(Value::Nil)
-}
 
-fn _fn(list: &Vec<Value>) -> Result<Value, String> {
-    let args = match &list[1] {
-        Value::List(l) => {
-            let mut args = vec![];
-            for arg in l {
-                match arg {
-                    Value::Symbol(s) => args.push(s.clone()),
-                    _ => return Err("Invalid function argument".to_string()),
-                }
-            }
-            args
+    #[test]
+    fn test_named_let() {
+        let mut env = Rc::new(RefCell::new(Environment::new()));
+        let source = "(let loop ((i 0) (acc 0))
+                        (if (eq i 20000) acc (loop (+ i 1) (+ acc i))))";
+        let res = evaluate(source, &mut env).unwrap();
+        assert_eq!(res, Value::Number(199990000.0));
+    }
+
+    #[test]
+    fn test_fn_body_sequence() {
+        let mut env = Rc::new(RefCell::new(Environment::new()));
+        let res = evaluate("((fn (x) (define y (* x 2)) (+ x y)) 3)", &mut env).unwrap();
+        assert_eq!(res, Value::Number(9.0));
+    }
+
+    #[test]
+    fn test_set_enclosing_scope() {
+        let mut env = Rc::new(RefCell::new(Environment::new()));
+        let source = "(define make-counter (fn ()
+                        (let ((n 0))
+                          (fn () (set! n (+ n 1)) n))))
+                      (define counter (make-counter))
+                      (counter)
+                      (counter)
+                      (counter)";
+        let res = evaluate(source, &mut env).unwrap();
+        assert_eq!(
+            res,
+            Value::List(vec![Value::Number(1.0), Value::Number(2.0), Value::Number(3.0)].into())
+        );
+    }
+
+    #[test]
+    fn test_set_global() {
+        let mut env = Rc::new(RefCell::new(Environment::new()));
+        let source = "(define x 1)
+                      (let ((y 2)) (set! x (+ x y)))
+                      x";
+        let res = evaluate(source, &mut env).unwrap();
+        assert_eq!(res, Value::List(vec![Value::Number(3.0)].into()));
+    }
+
+    #[test]
+    fn test_set_unbound() {
+        let mut env = Rc::new(RefCell::new(Environment::new()));
+        assert!(evaluate("(set! x 1)", &mut env).is_err());
+    }
+
+    #[test]
+    fn test_quote() {
+        let mut env = Rc::new(RefCell::new(Environment::new()));
+        let res = evaluate("'(a (b 1))", &mut env).unwrap();
+        assert_eq!(
+            res,
+            Value::List(
+                vec![
+                    Value::Symbol(Symbol::new("a")),
+                    Value::List(vec![Value::Symbol(Symbol::new("b")), Value::Number(1.0)].into())
+                ]
+                .into()
+            )
+        );
+    }
+
+    #[test]
+    fn test_quasiquote() {
+        let mut env = Rc::new(RefCell::new(Environment::new()));
+        let res = evaluate("(define xs (list 2 3)) `(1 ,@xs ,(+ 2 2) `(,x))", &mut env).unwrap();
+        assert_eq!(
+            res,
+            Value::List(
+                vec![Value::List(
+                    vec![
+                        Value::Number(1.0),
+                        Value::Number(2.0),
+                        Value::Number(3.0),
+                        Value::Number(4.0),
+                        Value::List(
+                            vec![
+                                Value::Symbol(Symbol::new("quasiquote")),
+                                Value::List(
+                                    vec![Value::List(
+                                        vec![
+                                            Value::Symbol(Symbol::new("unquote")),
+                                            Value::Symbol(Symbol::new("x"))
+                                        ]
+                                        .into()
+                                    )]
+                                    .into()
+                                )
+                            ]
+                            .into()
+                        )
+                    ]
+                    .into()
+                )]
+                .into()
+            )
+        );
+    }
+
+    #[test]
+    fn test_rest_arguments() {
+        let mut env = Rc::new(RefCell::new(Environment::new()));
+        let res = evaluate("((fn (a &rest more) (cons a more)) 1 2 3)", &mut env).unwrap();
+        assert_eq!(
+            res,
+            Value::List(vec![Value::Number(1.0), Value::Number(2.0), Value::Number(3.0)].into())
+        );
+        assert!(evaluate("((fn (a &rest more) a))", &mut env).is_err());
+    }
+
+    #[test]
+    fn test_try_catches_interpreter_errors() {
+        let mut env = Rc::new(RefCell::new(Environment::new()));
+        let source = "(try (+ 1 undefined)
+                        (catch e (list (error-kind e) (error-message e))))";
+        let result = evaluate(source, &mut env).unwrap();
+        assert_eq!(
+            result,
+            Value::List(
+                vec![
+                    Value::Symbol(Symbol::new("unbound-variable")),
+                    Value::String("Unbound symbol undefined".to_string())
+                ]
+                .into()
+            )
+        );
+    }
+
+    #[test]
+    fn test_try_catches_raised_values() {
+        let mut env = Rc::new(RefCell::new(Environment::new()));
+        let source = "(try (raise 42) (catch e (+ e 1)))";
+        let result = evaluate(source, &mut env).unwrap();
+        assert_eq!(result, Value::Number(43.0));
+    }
+
+    #[test]
+    fn test_error_with_kind_and_data() {
+        let mut env = Rc::new(RefCell::new(Environment::new()));
+        let source = r#"(try (error 'not-found "No such key" 'k)
+                          (catch e (list (error? e) (error-kind e) (error-data e))))"#;
+        let result = evaluate(source, &mut env).unwrap();
+        assert_eq!(
+            result,
+            Value::List(
+                vec![
+                    Value::Number(1.0),
+                    Value::Symbol(Symbol::new("not-found")),
+                    Value::Symbol(Symbol::new("k"))
+                ]
+                .into()
+            )
+        );
+    }
+
+    #[test]
+    fn test_rethrow() {
+        let mut env = Rc::new(RefCell::new(Environment::new()));
+        let source = r#"(try (try (error "inner") (catch e (raise e)))
+                          (catch e (error-message e)))"#;
+        let result = evaluate(source, &mut env).unwrap();
+        assert_eq!(result, Value::String("inner".to_string()));
+    }
+
+    #[test]
+    fn test_uncaught_error() {
+        let mut env = Rc::new(RefCell::new(Environment::new()));
+        let err = evaluate(r#"(error 'oops "Something broke")"#, &mut env).unwrap_err();
+        assert_eq!(err.to_string(), "oops: Something broke");
+    }
+
+    #[test]
+    fn test_finally() {
+        let mut env = Rc::new(RefCell::new(Environment::new()));
+        let source = r#"(define log '())
+                        (define result
+                          (try (set! log (cons 'body log))
+                               (error "fail")
+                               (catch e (set! log (cons 'catch log)) 1)
+                               (finally (set! log (cons 'finally log)) 2)))
+                        (list result log)"#;
+        let result = evaluate(source, &mut env).unwrap();
+        assert_eq!(
+            result,
+            Value::List(
+                vec![Value::List(
+                    vec![
+                        Value::Number(1.0),
+                        Value::List(
+                            vec![
+                                Value::Symbol(Symbol::new("finally")),
+                                Value::Symbol(Symbol::new("catch")),
+                                Value::Symbol(Symbol::new("body"))
+                            ]
+                            .into()
+                        )
+                    ]
+                    .into()
+                )]
+                .into()
+            )
+        );
+    }
+
+    #[test]
+    fn test_finally_without_catch_propagates() {
+        let mut env = Rc::new(RefCell::new(Environment::new()));
+        let source = r#"(define done 0)
+                        (try (try (raise 'boom) (finally (set! done 1)))
+                             (catch e (list e done)))"#;
+        let result = evaluate(source, &mut env).unwrap();
+        assert_eq!(
+            result,
+            Value::List(
+                vec![Value::List(
+                    vec![Value::Symbol(Symbol::new("boom")), Value::Number(1.0)].into()
+                )]
+                .into()
+            )
+        );
+    }
+
+    #[test]
+    fn test_handler_runs_before_unwinding() {
+        let mut env = Rc::new(RefCell::new(Environment::new()));
+        let source = r#"(define seen '())
+                        (define result
+                          (try (handler-bind ((oops (fn (c) (set! seen (cons 'handler seen)))))
+                                 (error 'oops "Failed"))
+                               (catch e (set! seen (cons 'catch seen)) (error-kind e))))
+                        (list result seen)"#;
+        let result = evaluate(source, &mut env).unwrap();
+        assert_eq!(
+            result,
+            Value::List(
+                vec![Value::List(
+                    vec![
+                        Value::Symbol(Symbol::new("oops")),
+                        Value::List(
+                            vec![
+                                Value::Symbol(Symbol::new("catch")),
+                                Value::Symbol(Symbol::new("handler"))
+                            ]
+                            .into()
+                        )
+                    ]
+                    .into()
+                )]
+                .into()
+            )
+        );
+    }
+
+    #[test]
+    fn test_restart_case() {
+        let mut env = Rc::new(RefCell::new(Environment::new()));
+        let source = r#"(define parse
+                          (fn (x)
+                            (restart-case (if (gt x 0) x (error 'bad-input "Negative" x))
+                              (use-value (v) v)
+                              (skip () nil))))
+                        (handler-bind ((bad-input (fn (c) (invoke-restart 'use-value (- 0 (error-data c))))))
+                          (map parse (list 1 -2 3)))"#;
+        let result = evaluate(source, &mut env).unwrap();
+        assert_eq!(
+            result,
+            Value::List(
+                vec![Value::List(
+                    vec![Value::Number(1.0), Value::Number(2.0), Value::Number(3.0)].into()
+                )]
+                .into()
+            )
+        );
+    }
+
+    #[test]
+    fn test_declining_handlers() {
+        let mut env = Rc::new(RefCell::new(Environment::new()));
+        let source = r#"(define count 0)
+                        (handler-bind ((condition (fn (c) (set! count (+ count 10)))))
+                          (handler-bind ((warning (fn (c) (set! count (+ count 1)))))
+                            (signal (make-condition 'warning "Careful"))
+                            (signal 'other)))
+                        count"#;
+        let result = evaluate(source, &mut env).unwrap();
+        assert_eq!(result, Value::List(vec![Value::Number(21.0)].into()));
+    }
+
+    #[test]
+    fn test_handler_does_not_see_its_own_errors() {
+        let mut env = Rc::new(RefCell::new(Environment::new()));
+        let source = r#"(try (handler-bind ((error (fn (c) (error 'inner "From handler"))))
+                               (error 'outer "From body"))
+                             (catch e (error-kind e)))"#;
+        let result = evaluate(source, &mut env).unwrap();
+        assert_eq!(result, Value::Symbol(Symbol::new("inner")));
+    }
+
+    #[test]
+    fn test_handlers_run_after_the_raising_step() {
+        let mut env = Rc::new(RefCell::new(Environment::new()));
+        let source = "(define v [1 2])
+                      (handler-bind ((index-error (fn (c) (set! v (vector-set! v 0 9)))))
+                        (vector-ref v 5))";
+        match evaluate(source, &mut env) {
+            Err(Exception::Raise(Value::Error(e))) => assert_eq!(e.kind, "index-error"),
+            other => panic!("expected an index error, got {:?}", other),
         }
-        _ => return Err("Invalid function".to_string()),
-    };
-    let body = match &list[2] {
-        Value::List(l) => l.clone(),
-        _ => return Err("Invalid function".to_string()),
-    };
-    Ok(Value::Lambda(args, body))
-}
-
-fn call(s: &str, list: &Vec<Value>, env: &mut Env) -> Result<Value, String> {
-    let lamdba = env.borrow_mut().get(s);
-    if let Some(func) = lamdba {
-        match func {
-            Value::Lambda(args, body) => {
-                let mut new_env = Rc::new(RefCell::new(Environment::extend(env.clone())));
-                for (i, arg) in args.iter().enumerate() {
-                    let val = value(&list[i + 1], env)?;
-                    new_env.borrow_mut().set(arg, val);
-                }
-                return value(&Value::List(body), &mut new_env);
-            }
-            _ => return Err(format!("Not a lambda: {}", s)),
+        assert_eq!(evaluate("v", &mut env).unwrap().to_string(), "[9 2]");
+        let source = "(define n 3)
+                      (handler-bind ((error (fn (c) n))) (set! zzz 1))";
+        match evaluate(source, &mut env) {
+            Err(Exception::Raise(Value::Error(e))) => assert_eq!(e.kind, "unbound-variable"),
+            other => panic!("expected an unbound variable error, got {:?}", other),
         }
-    } else {
-        return Err(format!("Unbound symbol {}", s));
     }
-}
 
-fn cond(conds: &[Value], env: &mut Env) -> Result<Value, String> {
-    for cond in &conds[1 .. conds.len()-1] {
-        if let Value::List(cs) = cond {
-            let res = value(&cs[0], env)?;
-            if let Value::Number(n) = res {
-                if n != 0.0 {
-                    return value(&cs[1], env);
-                }
-            }
-        }
+    #[test]
+    fn test_invoke_inactive_restart() {
+        let mut env = Rc::new(RefCell::new(Environment::new()));
+        let source = "(list (restart-case (compute-restarts) (a () 1) (b () 2))
+                            (compute-restarts))";
+        let result = evaluate(source, &mut env).unwrap();
+        assert_eq!(
+            result,
+            Value::List(
+                vec![
+                    Value::List(
+                        vec![
+                            Value::Symbol(Symbol::new("b")),
+                            Value::Symbol(Symbol::new("a"))
+                        ]
+                        .into()
+                    ),
+                    Value::List(List::new())
+                ]
+                .into()
+            )
+        );
+        let err = evaluate("(invoke-restart 'a)", &mut env).unwrap_err();
+        assert_eq!(err.to_string(), "control-error: No active restart named a");
     }
-    value(conds.last().unwrap(), env)
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    fn numbers(ns: &[f64]) -> Value {
+        Value::List(ns.iter().map(|n| Value::Number(*n)).collect())
+    }
 
     #[test]
-    fn test_add() {
+    fn test_call_cc_escape() {
         let mut env = Rc::new(RefCell::new(Environment::new()));
-        let result = evaluate("(+ 1 1)", &mut env).unwrap();
-        assert_eq!(result, Value::Number(2.0));
+        let source = "(call/cc (fn (return)
+                        (for-each (fn (x) (when (gt x 2) (return x))) (list 1 2 3 4))
+                        0))";
+        let result = evaluate(source, &mut env).unwrap();
+        assert_eq!(result, Value::Number(3.0));
     }
 
     #[test]
-    fn test_sub() {
+    fn test_call_cc_reentry() {
         let mut env = Rc::new(RefCell::new(Environment::new()));
-        let result = evaluate("(- 2 1)", &mut env).unwrap();
-        assert_eq!(result, Value::Number(1.0));
+        let source = "(let ((seen '()) (k 0))
+                        (set! seen (cons (call/cc (fn (c) (set! k c) 0)) seen))
+                        (if (lt (length seen) 3) (k (length seen)) seen))";
+        let result = evaluate(source, &mut env).unwrap();
+        assert_eq!(result, numbers(&[2.0, 1.0, 0.0]));
     }
 
     #[test]
-    fn test_mul() {
+    fn test_call_cc_reentry_inside_sort() {
         let mut env = Rc::new(RefCell::new(Environment::new()));
-        let result = evaluate("(* 2.5 2)", &mut env).unwrap();
-        assert_eq!(result, Value::Number(5.0));
+        let source = "(define k 0)
+                      (define saved 0)
+                      (define again 1)
+                      (define result
+                        (sort (list 2 1)
+                              (fn (a b)
+                                (call/cc (fn (c)
+                                           (when (eq saved 0) (set! saved 1) (set! k c))
+                                           (lt a b))))))
+                      (define first result)
+                      (when again (set! again 0) (k 0))
+                      (list first result)";
+        let result = evaluate(source, &mut env).unwrap();
+        assert_eq!(result.to_string(), "(((1 2) (2 1)))");
     }
 
     #[test]
-    fn test_div() {
+    fn test_call_cc_reentry_inside_map() {
         let mut env = Rc::new(RefCell::new(Environment::new()));
-        let result = evaluate("(/ 5 2)", &mut env).unwrap();
-        assert_eq!(result, Value::Number(2.5));
+        let source = "(define k 0)
+                      (define again 1)
+                      (define result
+                        (map (fn (x) (call/cc (fn (c) (when (eq x 2) (set! k c)) x)))
+                             (list 1 2 3)))
+                      (define first result)
+                      (when again (set! again 0) (k 20))
+                      (list first result)";
+        let result = evaluate(source, &mut env).unwrap();
+        assert_eq!(
+            result,
+            Value::List(
+                vec![Value::List(
+                    vec![numbers(&[1.0, 2.0, 3.0]), numbers(&[1.0, 20.0, 3.0])].into()
+                )]
+                .into()
+            )
+        );
     }
 
     #[test]
-    fn test_gt() {
+    fn test_continuation_escapes_from_handler() {
         let mut env = Rc::new(RefCell::new(Environment::new()));
-        let result = evaluate("(gt 5 2)", &mut env).unwrap();
-        assert_eq!(result, Value::Number(1.0));
+        let source = r#"(call/cc (fn (k)
+                          (handler-bind ((error (fn (c) (k (error-message c)))))
+                            (error "Escaped"))))"#;
+        let result = evaluate(source, &mut env).unwrap();
+        assert_eq!(result, Value::String("Escaped".to_string()));
     }
 
     #[test]
-    fn test_gte() {
+    fn test_dynamic_wind() {
         let mut env = Rc::new(RefCell::new(Environment::new()));
-        let result = evaluate("(gte 5 5)", &mut env).unwrap();
-        assert_eq!(result, Value::Number(1.0));
+        let source = "(define trace '())
+                      (define note (fn (x) (set! trace (cons x trace))))
+                      (define k 0)
+                      (define again 1)
+                      (dynamic-wind (fn () (note 'in))
+                                    (fn () (call/cc (fn (c) (set! k c))) (note 'body))
+                                    (fn () (note 'out)))
+                      (when again (set! again 0) (k 0))
+                      (call/cc (fn (escape)
+                        (dynamic-wind (fn () (note 'in))
+                                      (fn () (escape 0) (note 'skipped))
+                                      (fn () (note 'out)))))
+                      trace";
+        let result = evaluate(source, &mut env).unwrap();
+        let expected = ["out", "in", "out", "body", "in", "out", "body", "in"];
+        assert_eq!(
+            result,
+            Value::List(
+                vec![
+                    Value::Number(0.0),
+                    Value::List(
+                        expected
+                            .iter()
+                            .map(|s| Value::Symbol(Symbol::new(s)))
+                            .collect()
+                    )
+                ]
+                .into()
+            )
+        );
     }
 
     #[test]
-    fn test_gte_neg() {
+    fn test_deep_recursion_does_not_use_the_native_stack() {
         let mut env = Rc::new(RefCell::new(Environment::new()));
-        let result = evaluate("(gte 4 5)", &mut env).unwrap();
-        assert_eq!(result, Value::Number(0.0));
+        let source = "(define count (fn (n) (if (eq n 0) 0 (+ 1 (count (- n 1))))))
+                      (count 100000)";
+        let result = evaluate(source, &mut env).unwrap();
+        assert_eq!(result, Value::List(vec![Value::Number(100000.0)].into()));
     }
 
     #[test]
-    fn test_lt() {
+    fn test_reset_shift() {
         let mut env = Rc::new(RefCell::new(Environment::new()));
-        let result = evaluate("(lt 4 5)", &mut env).unwrap();
-        assert_eq!(result, Value::Number(1.0));
+        let source = "(list (+ 1 (reset (* 2 (shift k (k (k 5))))))
+                            (reset (+ 1 (shift k 10)))
+                            (reset 3))";
+        let result = evaluate(source, &mut env).unwrap();
+        assert_eq!(result, numbers(&[21.0, 10.0, 3.0]));
+        assert!(evaluate("(shift k 1)", &mut env).is_err());
     }
 
     #[test]
-    fn test_eq() {
+    fn test_shift_generator() {
         let mut env = Rc::new(RefCell::new(Environment::new()));
-        let result = evaluate("(eq 4 4)", &mut env).unwrap();
-        assert_eq!(result, Value::Number(1.0));
+        let source = "(define yield (fn (x) (shift k (list x k))))
+                      (define walk (fn (tree)
+                        (for-each (fn (node)
+                                    (if (eq (length node) 1) (yield (car node)) (walk node)))
+                                  tree)))
+                      (define start (fn (tree) (reset (walk tree) '())))
+                      (define drain (fn (gen)
+                        (if (eq (length gen) 0)
+                            '()
+                            (cons (car gen) (drain ((car (cdr gen))))))))
+                      (drain (start '((1) ((2) (3)) (4))))";
+        let result = evaluate(source, &mut env).unwrap();
+        assert_eq!(
+            result,
+            Value::List(vec![numbers(&[1.0, 2.0, 3.0, 4.0])].into())
+        );
     }
 
     #[test]
-    fn test_eq_neg() {
+    fn test_shift_async_await() {
         let mut env = Rc::new(RefCell::new(Environment::new()));
-        let result = evaluate("(eq 4 5)", &mut env).unwrap();
-        assert_eq!(result, Value::Number(0.0));
+        let source = "(define log '())
+                      (define say (fn (x) (set! log (cons x log))))
+                      (define await (fn (x) (shift k (list 'waiting x k))))
+                      (define spawn (fn (task) (reset (list 'done (task)))))
+                      (define task (fn (name x)
+                        (fn ()
+                          (say (list name 'start))
+                          (let ((y (await x)))
+                            (say (list name 'got y))
+                            (* y 10)))))
+                      (define schedule (fn (tasks results)
+                        (if (eq (length tasks) 0)
+                            results
+                            (let ((t (car tasks)))
+                              (case (car t)
+                                ((done) (schedule (cdr tasks) (cons (car (cdr t)) results)))
+                                (else (let ((resumed ((car (cdr (cdr t))) (car (cdr t)))))
+                                        (schedule (fold-right cons (list resumed) (cdr tasks))
+                                                  results))))))))
+                      (define results (schedule (list (spawn (task 'a 1)) (spawn (task 'b 2))) '()))
+                      (list results log)";
+        let result = evaluate(source, &mut env).unwrap();
+        let entry = |name: &str, what: &str, x: Option<f64>| {
+            let mut entry = vec![
+                Value::Symbol(Symbol::new(name)),
+                Value::Symbol(Symbol::new(what)),
+            ];
+            entry.extend(x.map(Value::Number));
+            Value::List(entry.into())
+        };
+        assert_eq!(
+            result,
+            Value::List(
+                vec![Value::List(
+                    vec![
+                        numbers(&[20.0, 10.0]),
+                        Value::List(
+                            vec![
+                                entry("b", "got", Some(2.0)),
+                                entry("a", "got", Some(1.0)),
+                                entry("b", "start", None),
+                                entry("a", "start", None),
+                            ]
+                            .into()
+                        )
+                    ]
+                    .into()
+                )]
+                .into()
+            )
+        );
     }
 
     #[test]
-    fn test_not() {
+    fn test_generator_next() {
         let mut env = Rc::new(RefCell::new(Environment::new()));
-        let result = evaluate("(not 1)", &mut env).unwrap();
-        assert_eq!(result, Value::Number(0.0));
+        let source = "(define naturals (make-generator (fn ()
+                        (let loop ((n 0)) (yield n) (loop (+ n 1))))))
+                      (next naturals)
+                      (next naturals)
+                      (next naturals)";
+        let result = evaluate(source, &mut env).unwrap();
+        assert_eq!(result, numbers(&[0.0, 1.0, 2.0]));
     }
 
     #[test]
-    fn test_not2() {
+    fn test_generator_exhausted() {
         let mut env = Rc::new(RefCell::new(Environment::new()));
-        let result = evaluate("(not 0)", &mut env).unwrap();
-        assert_eq!(result, Value::Number(1.0));
+        let source = "(define g (make-generator (fn () (yield 1) (yield 2))))
+                      (list (next g) (generator-done? g) (next g) (next g 'end)
+                            (generator-done? g) (next g 'end))";
+        let result = evaluate(source, &mut env).unwrap();
+        let end = Value::Symbol(Symbol::new("end"));
+        assert_eq!(
+            result,
+            Value::List(
+                vec![Value::List(
+                    vec![
+                        Value::Number(1.0),
+                        Value::Number(0.0),
+                        Value::Number(2.0),
+                        end.clone(),
+                        Value::Number(1.0),
+                        end,
+                    ]
+                    .into()
+                )]
+                .into()
+            )
+        );
+        match evaluate("(next g)", &mut env) {
+            Err(Exception::Raise(Value::Error(e))) => assert_eq!(e.kind, "generator-exhausted"),
+            other => panic!("expected generator-exhausted, got {:?}", other),
+        }
     }
 
     #[test]
-    fn test_not_eq() {
+    fn test_generator_for_each_pipeline() {
         let mut env = Rc::new(RefCell::new(Environment::new()));
-        let result = evaluate("(not (eq 1 0))", &mut env).unwrap();
-        assert_eq!(result, Value::Number(1.0));
+        let source = "(define walk (fn (tree)
+                        (for-each (fn (node)
+                                    (if (eq (length node) 1) (yield (car node)) (walk node)))
+                                  tree)))
+                      (define leaves (make-generator (fn () (walk '((1) ((2) (3)) (4))))))
+                      (define squares (fn (g)
+                        (make-generator (fn () (for-each (fn (x) (yield (* x x))) g)))))
+                      (define out '())
+                      (for-each (fn (x) (set! out (cons x out))) (squares leaves))
+                      out";
+        let result = evaluate(source, &mut env).unwrap();
+        assert_eq!(
+            result,
+            Value::List(vec![numbers(&[16.0, 9.0, 4.0, 1.0])].into())
+        );
     }
 
     #[test]
-    fn test_not_gt() {
+    fn test_delay_force_memoizes() {
         let mut env = Rc::new(RefCell::new(Environment::new()));
-        let result = evaluate("(not (gt 0 1))", &mut env).unwrap();
-        assert_eq!(result, Value::Number(1.0));
+        let source = "(define count 0)
+                      (define p (delay (compute)))
+                      (define compute (fn () (set! count (+ count 1)) (* count 10)))
+                      (list (promise? p) (force p) (force p) count
+                            (force (make-promise 5)) (force 7))";
+        let result = evaluate(source, &mut env).unwrap();
+        assert_eq!(
+            result,
+            Value::List(vec![numbers(&[1.0, 10.0, 10.0, 1.0, 5.0, 7.0])].into())
+        );
     }
 
     #[test]
-    fn test_simple_program() {
-        let mut env= Rc::new(RefCell::new(Environment::new()));
-        let source = "(let b 10)
-                      (let h 14)
-                      (/ (* b h) 2)";
-        let res = evaluate(source, &mut env).unwrap();
-        assert_eq!(res, Value::List(vec![Value::Number(70.0)]));
+    fn test_infinite_streams() {
+        let mut env = Rc::new(RefCell::new(Environment::new()));
+        let source = "(define integers-from (fn (n) (stream-cons n (integers-from (+ n 1)))))
+                      (define sieve (fn (s)
+                        (let ((p (stream-car s)))
+                          (stream-cons p (sieve (stream-filter (fn (x) (not (eq (remainder x p) 0)))
+                                                               (stream-cdr s)))))))
+                      (define remainder (fn (x p) (if (lt x p) x (remainder (- x p) p))))
+                      (list (stream-take (stream-map (fn (x) (* x x)) (integers-from 1)) 5)
+                            (stream-take (sieve (integers-from 2)) 6)
+                            (stream-take (stream-cons 1 '()) 3))";
+        let result = evaluate(source, &mut env).unwrap();
+        assert_eq!(
+            result,
+            Value::List(
+                vec![Value::List(
+                    vec![
+                        numbers(&[1.0, 4.0, 9.0, 16.0, 25.0]),
+                        numbers(&[2.0, 3.0, 5.0, 7.0, 11.0, 13.0]),
+                        numbers(&[1.0]),
+                    ]
+                    .into()
+                )]
+                .into()
+            )
+        );
     }
 
     #[test]
-    fn test_conditional() {
-        let mut env= Rc::new(RefCell::new(Environment::new()));
-        let source = "(let factorial (fn (n) (cond ((lt n 1) 1) (* n (factorial (- n 1))))))(factorial 5)";
-        let res = evaluate(source, &mut env).unwrap();
-        assert_eq!(res, Value::List(vec![Value::Number(120.0)]));
+    fn test_while() {
+        let mut env = Rc::new(RefCell::new(Environment::new()));
+        let source = "(define i 0)
+                      (define sum 0)
+                      (while (lt i 100000)
+                        (set! sum (+ sum i))
+                        (set! i (+ i 1)))
+                      sum";
+        let result = evaluate(source, &mut env).unwrap();
+        assert_eq!(
+            result,
+            Value::List(vec![Value::Number(4999950000.0)].into())
+        );
+    }
+
+    #[test]
+    fn test_dotimes_dolist() {
+        let mut env = Rc::new(RefCell::new(Environment::new()));
+        let source = "(define out '())
+                      (dotimes (i 3) (set! out (cons i out)))
+                      (dolist (x '(a b)) (set! out (cons x out)))
+                      (list out (dotimes (i 4 i)) (dolist (x '(1 2) 'done)))";
+        let result = evaluate(source, &mut env).unwrap();
+        let sym = |s: &str| Value::Symbol(Symbol::new(s));
+        assert_eq!(
+            result,
+            Value::List(
+                vec![Value::List(
+                    vec![
+                        Value::List(
+                            vec![
+                                sym("b"),
+                                sym("a"),
+                                Value::Number(2.0),
+                                Value::Number(1.0),
+                                Value::Number(0.0),
+                            ]
+                            .into()
+                        ),
+                        Value::Number(4.0),
+                        sym("done"),
+                    ]
+                    .into()
+                )]
+                .into()
+            )
+        );
+    }
+
+    #[test]
+    fn test_loop_break_continue() {
+        let mut env = Rc::new(RefCell::new(Environment::new()));
+        let source = "(define i 0)
+                      (define odds '())
+                      (define half (fn (n) (if (lt n 2) 0 (+ 1 (half (- n 2))))))
+                      (define found
+                        (loop
+                          (set! i (+ i 1))
+                          (when (gt i 9) (break i))
+                          (when (eq (- i (* 2 (half i))) 0) (continue))
+                          (set! odds (cons i odds))))
+                      (list found odds
+                            (dotimes (j 5) (dolist (x '(1 2 3)) (when (eq x 2) (break))) (when (eq j 3) (break 'out))))";
+        let result = evaluate(source, &mut env).unwrap();
+        assert_eq!(
+            result,
+            Value::List(
+                vec![Value::List(
+                    vec![
+                        Value::Number(10.0),
+                        numbers(&[9.0, 7.0, 5.0, 3.0, 1.0]),
+                        Value::Symbol(Symbol::new("out")),
+                    ]
+                    .into()
+                )]
+                .into()
+            )
+        );
+        match evaluate("(break 1)", &mut env) {
+            Err(Exception::Break(val)) => assert_eq!(val, Value::Number(1.0)),
+            other => panic!("expected break, got {:?}", other),
+        }
+        let source = "(define loop 1)
+                      (list (loop (break 5))
+                            (let loop ((n 0)) (if (eq n 3) n (loop (+ n 1))))
+                            (let loop ((n 0)) (if (eq n 1) (list 'loop `(loop ,n)) (loop (+ n 1))))
+                            (let again ((n 0)) (if (eq n 2) (loop (break n)) (again (+ n 1)))))";
+        let result = evaluate(source, &mut env).unwrap();
+        assert_eq!(result.to_string(), "((5 3 (loop (loop 1)) 2))");
+    }
+
+    #[test]
+    fn test_pairs_as_code() {
+        let mut env = Rc::new(RefCell::new(Environment::new()));
+        let source = "(defmacro swap-args (call) (cons (car call) (cons (car (cdr (cdr call))) (cons (car (cdr call)) '()))))
+                      (defmacro add-one-to (xs) `(+ ,@(cons 1 xs)))
+                      (defmacro improper () (cons '+ 1))
+                      (define build (fn (n acc) (if (eq n 0) acc (build (- n 1) (cons n acc)))))
+                      (list (swap-args (- 1 10)) (length (build 100000 '())) (add-one-to (2 3)))";
+        let result = evaluate(source, &mut env).unwrap();
+        assert_eq!(
+            result,
+            Value::List(vec![numbers(&[9.0, 100000.0, 6.0])].into())
+        );
+        assert!(evaluate("(improper)", &mut env).is_err());
+    }
+
+    #[test]
+    fn test_keyword_arguments() {
+        let mut env = Rc::new(RefCell::new(Environment::new()));
+        let source = "(define box (fn (w &key height depth) (list w height depth)))
+                      (list (box 1 :depth 3 :height 2) (box 1) (box 1 :depth :height))";
+        let result = evaluate(source, &mut env).unwrap();
+        assert_eq!(
+            result.to_string(),
+            "(((1 2 3) (1 nil nil) (1 nil :height)))"
+        );
+        assert!(evaluate("(box 1 :width 2)", &mut env).is_err());
+        assert!(evaluate("(box 1 :depth)", &mut env).is_err());
+        assert!(evaluate("(box)", &mut env).is_err());
+        assert!(evaluate("(fn (a &rest b &key c) a)", &mut env).is_err());
+    }
+
+    #[test]
+    fn test_defstruct() {
+        let mut env = Rc::new(RefCell::new(Environment::new()));
+        let source = "(defstruct point x y)
+                      (define p (make-point 1 2))
+                      (set-point-y! p 5)
+                      (list p (point-x p) (point-y p) (point? p) (point? 1))";
+        let result = evaluate(source, &mut env).unwrap();
+        assert_eq!(result.to_string(), "((#<point x: 1 y: 5> 1 5 1 0))");
+        assert_eq!(
+            evaluate("(make-point 1 5)", &mut env).unwrap(),
+            evaluate("p", &mut env).unwrap()
+        );
+        assert_ne!(
+            evaluate("(make-point 1 2)", &mut env).unwrap(),
+            evaluate("p", &mut env).unwrap()
+        );
+        assert!(evaluate("(point-x 5)", &mut env).is_err());
+        assert!(evaluate("(make-point 1)", &mut env).is_err());
+        let source = "(defstruct other x y)
+                      (list (eq (make-point 1 2) (make-point 1 2)) (eq (make-point 1 2) (make-point 2 1))
+                            (eq (make-point 1 2) (make-other 1 2)) (eq p 1))";
+        let result = evaluate(source, &mut env).unwrap();
+        assert_eq!(result.to_string(), "((1 0 0 0))");
+    }
+
+    #[test]
+    fn test_define_record_type() {
+        let mut env = Rc::new(RefCell::new(Environment::new()));
+        let source = "(define-record-type <account> (make-account owner) account?
+                        (owner account-owner)
+                        (balance account-balance set-account-balance!))
+                      (defstruct other owner balance)
+                      (define a (make-account 'ann))
+                      (set-account-balance! a 10)
+                      (list a (account-balance a) (account? a) (account? (make-other 'ann 10))
+                            <account>)";
+        let result = evaluate(source, &mut env).unwrap();
+        assert_eq!(
+            result.to_string(),
+            "((#<account owner: ann balance: 10> 10 1 0 #<record-type account>))"
+        );
+        assert!(evaluate("(account-owner (make-other 'ann 10))", &mut env).is_err());
+        assert!(evaluate("(define-record-type t (make-t z) t? (x t-x))", &mut env).is_err());
+    }
+
+    #[test]
+    fn test_match() {
+        let mut env = Rc::new(RefCell::new(Environment::new()));
+        let source = "(defstruct point x y)
+                      (define describe
+                        (fn (v)
+                          (match v
+                            (0 'zero)
+                            (\"hi\" 'greeting)
+                            (:none 'nothing)
+                            ('done 'finished)
+                            (() 'empty)
+                            ((point 0 y) (list 'on-y-axis y))
+                            ((point x y) :when (gt x y) (list 'below x y))
+                            ((point x _) (list 'point x))
+                            ([a b] (list 'pair a b))
+                            ({:name n} (list 'named n))
+                            ((op a b) :when (eq op '+) (+ a b))
+                            ((first . rest) (list 'head first rest))
+                            (n :when (gt n 100) 'big)
+                            (_ 'other))))
+                      (map describe
+                           (list 0 \"hi\" :none 'done '() (make-point 0 3) (make-point 5 1)
+                                 (make-point 1 5) [1 2] {:name 'ann :age 3} '(+ 1 2) '(1 2 3)
+                                 (cons 1 2) 500 7))";
+        let result = evaluate(source, &mut env).unwrap();
+        assert_eq!(
+            result.to_string(),
+            "((zero greeting nothing finished empty (on-y-axis 3) (below 5 1) (point 1) \
+              (pair 1 2) (named ann) 3 (head 1 (2 3)) (head 1 2) big other))"
+        );
+        match evaluate("(match 5 (1 'one))", &mut env) {
+            Err(Exception::Raise(Value::Error(e))) => assert_eq!(e.kind, "match-error"),
+            other => panic!("expected a match error, got {:?}", other),
+        }
+        assert!(evaluate("(match (make-point 1 2) ((point x) x))", &mut env).is_err());
+    }
+
+    #[test]
+    fn test_destructuring_let_and_params() {
+        let mut env = Rc::new(RefCell::new(Environment::new()));
+        let source = "(define dist (fn ((x1 y1) [x2 y2]) (+ (- x2 x1) (- y2 y1))))
+                      (let (((a . rest) '(1 2 3)) ({:k k} {:k 4}))
+                        (let* (((b c) rest) (d (+ b c)))
+                          (list a b c d k (dist '(1 1) [4 5]))))";
+        let result = evaluate(source, &mut env).unwrap();
+        assert_eq!(result.to_string(), "((1 2 3 5 4 7))");
+        assert!(evaluate("(let (((a b) '(1))) a)", &mut env).is_err());
+        assert!(evaluate("(dist '(1) [1 1])", &mut env).is_err());
+    }
+
+    #[test]
+    fn test_multiple_values() {
+        let mut env = Rc::new(RefCell::new(Environment::new()));
+        let source = "(define split-at (fn (l n) (values (take l n) (drop l n))))
+                      (receive (head tail) (split-at '(1 2 3) 1) (list head tail))
+                      (let-values (((a b) (split-at '(4 5) 1))
+                                   ((first &rest others) (values 1 2 3))
+                                   (all (values 6 7)))
+                        (list a b first others all))
+                      (call-with-values (fn () (split-at '(1 2) 2)) list)
+                      (call-with-values (fn () (call/cc (fn (k) (k 1 2)))) list)
+                      (list (car (split-at '(8 9) 1)) (values) (values 9))";
+        let result = evaluate(source, &mut env).unwrap();
+        assert_eq!(
+            result.to_string(),
+            "(((1) (2 3)) ((4) (5) 1 (2 3) (6 7)) ((1 2) ()) (1 2) (8 nil 9))"
+        );
+        assert_eq!(
+            evaluate("(values 1 2)", &mut env).unwrap().to_string(),
+            "1 2"
+        );
+        assert!(evaluate("(receive (a b) (values 1) a)", &mut env).is_err());
+    }
+
+    /// Times programs that mostly look up variables and compare symbols. Run
+    /// with `cargo test --release bench_ -- --ignored --nocapture`.
+    ///
+    /// Median of seven runs before and after symbols were interned, with the
+    /// same test copied into the string-keyed tree:
+    ///
+    /// | program         | strings | interned |
+    /// |-----------------|---------|----------|
+    /// | fib             |  305ms  |   215ms  |
+    /// | nested scopes   |  290ms  |   185ms  |
+    /// | symbol compares |  205ms  |   150ms  |
+    #[test]
+    #[ignore]
+    fn bench_symbol_lookups() {
+        let programs = [
+            (
+                "fib",
+                "(define fib (fn (n) (if (lt n 2) n (+ (fib (- n 1)) (fib (- n 2))))))
+                 (fib 24)",
+            ),
+            (
+                "nested scopes",
+                "(define total 0)
+                 (let ((a 1) (b 2))
+                   (let ((c 3) (d 4))
+                     (let ((e 5) (f 6))
+                       (dotimes (i 200000)
+                         (set! total (+ total a b c d e f))))))
+                 total",
+            ),
+            (
+                "symbol compares",
+                "(define count 0)
+                 (dolist (s '(alpha beta gamma delta alpha beta gamma delta))
+                   (dotimes (i 30000)
+                     (case s ((alpha gamma) (set! count (+ count 1))) (else 0))))
+                 count",
+            ),
+        ];
+        for (name, source) in programs {
+            let mut times = (0..7)
+                .map(|_| {
+                    let mut env = Rc::new(RefCell::new(Environment::new()));
+                    let start = std::time::Instant::now();
+                    evaluate(source, &mut env).unwrap();
+                    start.elapsed()
+                })
+                .collect::<Vec<_>>();
+            times.sort();
+            println!("{}: median {:?}, best {:?}", name, times[3], times[0]);
+        }
     }
 }
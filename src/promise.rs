@@ -0,0 +1,67 @@
+use std::{cell::RefCell, fmt, rc::Rc};
+
+use crate::{
+    error::Exception,
+    program::{then, Tail},
+    value::Value,
+};
+
+type Thunk = Rc<dyn Fn() -> Result<Tail, Exception>>;
+
+/// A value that is computed the first time it is forced, made by `delay`,
+/// `make-promise` or a stream. Later forces return the same value.
+pub struct Promise {
+    state: RefCell<State>,
+}
+
+#[derive(Clone)]
+enum State {
+    Delayed(Thunk),
+    Forced(Value),
+}
+
+impl Promise {
+    pub fn delay(thunk: impl Fn() -> Result<Tail, Exception> + 'static) -> Self {
+        Promise {
+            state: RefCell::new(State::Delayed(Rc::new(thunk))),
+        }
+    }
+
+    pub fn forced(val: Value) -> Self {
+        Promise {
+            state: RefCell::new(State::Forced(val)),
+        }
+    }
+
+    /// Computes the value unless that has been done before. If forcing the
+    /// promise forces it again, the value computed first is kept.
+    pub fn force(self: &Rc<Self>) -> Result<Tail, Exception> {
+        let thunk = match self.state.borrow().clone() {
+            State::Forced(val) => return Ok(Tail::Return(val)),
+            State::Delayed(thunk) => thunk,
+        };
+        let promise = self.clone();
+        Ok(then(thunk()?, move |val| {
+            let mut state = promise.state.borrow_mut();
+            match &*state {
+                State::Forced(first) => Ok(Tail::Return(first.clone())),
+                State::Delayed(_) => {
+                    *state = State::Forced(val.clone());
+                    Ok(Tail::Return(val))
+                }
+            }
+        }))
+    }
+}
+
+impl PartialEq for Promise {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+impl fmt::Debug for Promise {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Promise")
+    }
+}
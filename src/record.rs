@@ -0,0 +1,179 @@
+use std::{cell::RefCell, fmt, rc::Rc};
+
+use crate::{
+    error::Exception,
+    symbol::Symbol,
+    value::{Builtin, Primitive, Value},
+};
+
+/// A record type made by `defstruct` or `define-record-type`: its name and the
+/// names of its fields, in the order the constructor takes them.
+#[derive(Debug)]
+pub struct RecordType {
+    pub name: Symbol,
+    pub fields: Vec<Symbol>,
+}
+
+/// An instance of a record type. Modifiers change the fields in place.
+#[derive(Debug)]
+pub struct Record {
+    pub rtype: Rc<RecordType>,
+    pub fields: RefCell<Vec<Value>>,
+}
+
+impl RecordType {
+    /// The name without the angle brackets of a `define-record-type` name
+    /// like `<point>`.
+    pub fn display_name(&self) -> &'static str {
+        let name = self.name.name();
+        name.strip_prefix('<')
+            .and_then(|n| n.strip_suffix('>'))
+            .unwrap_or(name)
+    }
+}
+
+/// Records are equal when they are of the same type and their fields are
+/// equal.
+impl PartialEq for Record {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.rtype, &other.rtype) && self.fields == other.fields
+    }
+}
+
+impl fmt::Display for Record {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "#<{}", self.rtype.display_name())?;
+        for (name, val) in self.rtype.fields.iter().zip(self.fields.borrow().iter()) {
+            write!(f, " {}: {}", name, val)?;
+        }
+        write!(f, ">")
+    }
+}
+
+/// The functions generated for a record type are plain lambdas that call one
+/// of these primitives with the type as a constant argument.
+fn primitive(name: &'static str, func: fn(&[Value]) -> Result<Value, Exception>) -> Value {
+    Value::Builtin(Builtin {
+        name,
+        func: Primitive::Value(func),
+    })
+}
+
+fn lambda(params: &[Symbol], call: Vec<Value>) -> Value {
+    let env = Rc::new(RefCell::new(Default::default()));
+    Value::Lambda(params.to_vec(), vec![Value::List(call.into())], env)
+}
+
+/// `(make-point x y)`: takes the fields named `params`, which may be any of
+/// the fields in any order. The ones left out are `nil`.
+pub fn constructor(rtype: &Rc<RecordType>, params: &[Symbol]) -> Result<Value, Exception> {
+    if let Some(unknown) = params.iter().find(|p| !rtype.fields.contains(p)) {
+        return Err(Exception::new(
+            "syntax-error",
+            format!("{} has no field {}", rtype.display_name(), unknown),
+        ));
+    }
+    let mut call = vec![
+        primitive("make-record", make_record),
+        Value::RecordType(rtype.clone()),
+    ];
+    for field in &rtype.fields {
+        if params.contains(field) {
+            call.push(Value::Symbol(*field));
+        } else {
+            call.push(Value::Nil);
+        }
+    }
+    Ok(lambda(params, call))
+}
+
+/// `(point? v)`
+pub fn predicate(rtype: &Rc<RecordType>) -> Value {
+    let v = Symbol::new("v");
+    lambda(
+        &[v],
+        vec![
+            primitive("record-of?", is_record_of),
+            Value::RecordType(rtype.clone()),
+            Value::Symbol(v),
+        ],
+    )
+}
+
+/// `(point-x p)`
+pub fn accessor(rtype: &Rc<RecordType>, field: usize) -> Value {
+    let r = Symbol::new("record");
+    lambda(
+        &[r],
+        vec![
+            primitive("record-ref", record_ref),
+            Value::RecordType(rtype.clone()),
+            Value::Number(field as f64),
+            Value::Symbol(r),
+        ],
+    )
+}
+
+/// `(set-point-x! p x)`
+pub fn modifier(rtype: &Rc<RecordType>, field: usize) -> Value {
+    let (r, v) = (Symbol::new("record"), Symbol::new("v"));
+    lambda(
+        &[r, v],
+        vec![
+            primitive("record-set!", record_set),
+            Value::RecordType(rtype.clone()),
+            Value::Number(field as f64),
+            Value::Symbol(r),
+            Value::Symbol(v),
+        ],
+    )
+}
+
+fn make_record(args: &[Value]) -> Result<Value, Exception> {
+    match args {
+        [Value::RecordType(rtype), fields @ ..] => Ok(Value::Record(Rc::new(Record {
+            rtype: rtype.clone(),
+            fields: RefCell::new(fields.to_vec()),
+        }))),
+        _ => unreachable!("constructors pass their type"),
+    }
+}
+
+fn is_record_of(args: &[Value]) -> Result<Value, Exception> {
+    match args {
+        [Value::RecordType(rtype), v] => {
+            Ok(matches!(v, Value::Record(r) if Rc::ptr_eq(&r.rtype, rtype)).into())
+        }
+        _ => unreachable!("predicates pass their type"),
+    }
+}
+
+/// The record in `v`, if it is of type `rtype`.
+fn instance<'a>(rtype: &Rc<RecordType>, v: &'a Value) -> Result<&'a Rc<Record>, Exception> {
+    match v {
+        Value::Record(r) if Rc::ptr_eq(&r.rtype, rtype) => Ok(r),
+        _ => Err(Exception::new(
+            "type-error",
+            format!("Expected a {}, got {}", rtype.display_name(), v),
+        )),
+    }
+}
+
+fn record_ref(args: &[Value]) -> Result<Value, Exception> {
+    match args {
+        [Value::RecordType(rtype), Value::Number(i), v] => {
+            Ok(instance(rtype, v)?.fields.borrow()[*i as usize].clone())
+        }
+        _ => unreachable!("accessors pass their type and field"),
+    }
+}
+
+fn record_set(args: &[Value]) -> Result<Value, Exception> {
+    match args {
+        [Value::RecordType(rtype), Value::Number(i), v, val] => {
+            instance(rtype, v)?.fields.borrow_mut()[*i as usize] = val.clone();
+            Ok(Value::Nil)
+        }
+        _ => unreachable!("modifiers pass their type and field"),
+    }
+}
@@ -0,0 +1,105 @@
+use std::rc::Rc;
+
+/// A persistent stack. Pushing returns a new stack that shares the old one, so
+/// a copy can be kept and resumed later no matter what happens to the original.
+pub struct Stack<T>(Option<Rc<Node<T>>>);
+
+struct Node<T> {
+    value: T,
+    next: Stack<T>,
+}
+
+impl<T> Stack<T> {
+    pub fn new() -> Self {
+        Stack(None)
+    }
+
+    pub fn push(&self, value: T) -> Self {
+        Stack(Some(Rc::new(Node {
+            value,
+            next: self.clone(),
+        })))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &T> {
+        self.nodes().map(|(_, value)| value)
+    }
+
+    /// Every element together with an identity for its node, which is shared
+    /// by all the stacks the node is part of.
+    pub fn nodes(&self) -> impl Iterator<Item = (*const (), &T)> {
+        let mut next = self.0.as_ref();
+        std::iter::from_fn(move || {
+            let node = next?;
+            next = node.next.0.as_ref();
+            Some((Rc::as_ptr(node) as *const (), &node.value))
+        })
+    }
+}
+
+impl<T: Clone> Stack<T> {
+    pub fn pop(&mut self) -> Option<T> {
+        let node = self.0.take()?;
+        match Rc::try_unwrap(node) {
+            Ok(mut node) => {
+                self.0 = node.next.0.take();
+                Some(node.value)
+            }
+            Err(node) => {
+                self.0 = node.next.0.clone();
+                Some(node.value.clone())
+            }
+        }
+    }
+}
+
+impl<T> Clone for Stack<T> {
+    fn clone(&self) -> Self {
+        Stack(self.0.clone())
+    }
+}
+
+impl<T> Default for Stack<T> {
+    fn default() -> Self {
+        Stack::new()
+    }
+}
+
+// Dropping the nodes one at a time instead of recursively, deep stacks would
+// overflow the native stack otherwise.
+impl<T> Drop for Stack<T> {
+    fn drop(&mut self) {
+        let mut next = self.0.take();
+        while let Some(node) = next {
+            next = match Rc::try_unwrap(node) {
+                Ok(mut node) => node.next.0.take(),
+                Err(_) => None,
+            };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_push_shares_the_rest() {
+        let base = Stack::new().push(1).push(2);
+        let mut a = base.push(3);
+        let b = base.push(4);
+        assert_eq!(a.pop(), Some(3));
+        assert_eq!(a.iter().copied().collect::<Vec<_>>(), vec![2, 1]);
+        assert_eq!(b.iter().copied().collect::<Vec<_>>(), vec![4, 2, 1]);
+        assert_eq!(a.nodes().next().unwrap().0, b.nodes().nth(1).unwrap().0);
+    }
+
+    #[test]
+    fn test_deep_stack() {
+        let mut stack = Stack::new();
+        for i in 0..1_000_000 {
+            stack = stack.push(i);
+        }
+        assert_eq!(stack.iter().next(), Some(&999_999));
+    }
+}
@@ -0,0 +1,286 @@
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    fmt,
+    hash::{BuildHasherDefault, Hash, Hasher},
+    sync::atomic::{AtomicU32, Ordering},
+};
+
+thread_local! {
+    static SYMBOLS: RefCell<Table> = RefCell::new(Table::default());
+}
+
+/// Every name that has been read or made into a symbol, and its id.
+struct Table {
+    ids: HashMap<&'static str, u32>,
+    names: Vec<&'static str>,
+}
+
+impl Default for Table {
+    fn default() -> Self {
+        Table {
+            ids: known::NAMES
+                .iter()
+                .zip(0..)
+                .map(|(&name, id)| (name, id))
+                .collect(),
+            names: known::NAMES.to_vec(),
+        }
+    }
+}
+
+/// A symbol is the index of its name in a table that lives as long as the
+/// program, so copying, comparing and hashing symbols never touches the name.
+///
+/// Interned symbols have no mark. `gensym` and `syntax-rules` make uninterned
+/// ones, an interned symbol with a new mark, which are distinct from every
+/// other symbol without adding a name to the table.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct Symbol {
+    id: u32,
+    mark: u32,
+}
+
+impl Symbol {
+    pub fn new(name: &str) -> Self {
+        SYMBOLS.with(|table| {
+            let mut table = table.borrow_mut();
+            if let Some(&id) = table.ids.get(name) {
+                return Symbol { id, mark: 0 };
+            }
+            // Names are never removed from the table, so leaking them is what
+            // lets `name` hand out plain references.
+            let name: &'static str = Box::leak(name.into());
+            let id = table.names.len() as u32;
+            table.names.push(name);
+            table.ids.insert(name, id);
+            Symbol { id, mark: 0 }
+        })
+    }
+
+    /// A symbol named after `prefix` that is distinct from every other one.
+    pub fn gensym(prefix: &str) -> Self {
+        Symbol {
+            id: Symbol::new(prefix).id,
+            mark: new_mark(),
+        }
+    }
+
+    /// The symbol as written in a template of the `syntax-rules` expansion with
+    /// the given mark.
+    pub fn alias(self, mark: u32) -> Self {
+        Symbol {
+            id: self.id,
+            mark: mark | ALIAS,
+        }
+    }
+
+    /// The interned symbol an alias was made from, or the symbol itself.
+    pub fn original(self) -> Self {
+        match self.is_alias() {
+            true => Symbol {
+                id: self.id,
+                mark: 0,
+            },
+            false => self,
+        }
+    }
+
+    pub fn is_alias(self) -> bool {
+        self.mark & ALIAS != 0
+    }
+
+    /// Whether the symbol is one of the `known` names.
+    pub fn is_known(self) -> bool {
+        self.mark == 0 && (self.id as usize) < known::NAMES.len()
+    }
+
+    /// The name the symbol was made from. Symbols made by `gensym` or renamed
+    /// by `syntax-rules` share it with their interned symbol.
+    pub fn name(self) -> &'static str {
+        SYMBOLS.with(|table| table.borrow().names[self.id as usize])
+    }
+}
+
+/// Set on the mark of an alias made by `syntax-rules`.
+const ALIAS: u32 = 1 << 31;
+
+/// A new mark for `gensym` or for one expansion of a `syntax-rules` macro.
+pub fn new_mark() -> u32 {
+    static MARKS: AtomicU32 = AtomicU32::new(0);
+    (MARKS.fetch_add(1, Ordering::Relaxed) + 1) & !ALIAS
+}
+
+impl Hash for Symbol {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        state.write_u64(((self.mark as u64) << 32) | self.id as u64)
+    }
+}
+
+impl PartialEq<str> for Symbol {
+    fn eq(&self, other: &str) -> bool {
+        self.name() == other
+    }
+}
+
+impl PartialEq<&str> for Symbol {
+    fn eq(&self, other: &&str) -> bool {
+        self.name() == *other
+    }
+}
+
+impl fmt::Display for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.mark {
+            0 => write!(f, "{}", self.name()),
+            mark => write!(f, "#:{}{}", self.name(), mark & !ALIAS),
+        }
+    }
+}
+
+impl fmt::Debug for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}", self.to_string())
+    }
+}
+
+/// Defines a constant for each name, with the index of the name as its id.
+macro_rules! known {
+    ($($symbol:ident = $name:literal,)*) => {
+        pub(super) const NAMES: &[&str] = &[$($name),*];
+        known!(@ids 0; $($symbol)*);
+    };
+    (@ids $id:expr; $symbol:ident $($rest:ident)*) => {
+        pub const $symbol: Symbol = Symbol { id: $id, mark: 0 };
+        known!(@ids $id + 1; $($rest)*);
+    };
+    (@ids $id:expr;) => {};
+}
+
+/// The special forms and the other names the evaluator and the expander look
+/// for. They are interned before anything else, so their ids are constants
+/// that can be matched on.
+pub mod known {
+    use super::Symbol;
+
+    known! {
+        DEFINE = "define",
+        SET = "set!",
+        LET = "let",
+        LET_STAR = "let*",
+        LETREC = "letrec",
+        RECEIVE = "receive",
+        LET_VALUES = "let-values",
+        FN = "fn",
+        DEFMACRO = "defmacro",
+        DEFINE_SYNTAX = "define-syntax",
+        DEFSTRUCT = "defstruct",
+        DEFINE_RECORD_TYPE = "define-record-type",
+        MACROEXPAND_1 = "macroexpand-1",
+        MACROEXPAND = "macroexpand",
+        QUOTE = "quote",
+        QUASIQUOTE = "quasiquote",
+        UNQUOTE = "unquote",
+        UNQUOTE_SPLICING = "unquote-splicing",
+        COND = "cond",
+        CASE = "case",
+        MATCH = "match",
+        IF = "if",
+        WHEN = "when",
+        UNLESS = "unless",
+        AND = "and",
+        OR = "or",
+        TRY = "try",
+        CATCH = "catch",
+        FINALLY = "finally",
+        HANDLER_BIND = "handler-bind",
+        RESTART_CASE = "restart-case",
+        RESET = "reset",
+        SHIFT = "shift",
+        WHILE = "while",
+        DOTIMES = "dotimes",
+        DOLIST = "dolist",
+        LOOP = "loop",
+        DELAY = "delay",
+        STREAM_CONS = "stream-cons",
+        SYNTAX_RULES = "syntax-rules",
+        ELSE = "else",
+        ARROW = "=>",
+        WILDCARD = "_",
+        ELLIPSIS = "...",
+        REST = "&rest",
+        KEY = "&key",
+    }
+}
+
+/// Hashes a symbol by spreading the bits of its id, which is already unique,
+/// over the whole hash.
+#[derive(Default)]
+pub struct IdHasher(u64);
+
+impl Hasher for IdHasher {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for b in bytes {
+            self.0 = (self.0 << 8) | *b as u64;
+        }
+    }
+
+    fn write_u32(&mut self, n: u32) {
+        self.0 = (n as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15);
+    }
+
+    fn write_u64(&mut self, n: u64) {
+        self.0 = n.wrapping_mul(0x9e37_79b9_7f4a_7c15);
+    }
+}
+
+/// A map from symbols that skips hashing their ids.
+pub type SymbolMap<V> = HashMap<Symbol, V, BuildHasherDefault<IdHasher>>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_interned() {
+        let a = Symbol::new("car");
+        assert_eq!(a, Symbol::new(&String::from("car")));
+        assert_ne!(a, Symbol::new("cdr"));
+        assert_eq!(a.name(), "car");
+        assert!(a == "car");
+        let mut map = SymbolMap::default();
+        map.insert(a, 1);
+        assert_eq!(map.get(&Symbol::new("car")), Some(&1));
+    }
+
+    #[test]
+    fn test_known() {
+        assert_eq!(Symbol::new("define"), known::DEFINE);
+        assert_eq!(Symbol::new("&key"), known::KEY);
+        assert_eq!(known::LET_VALUES.name(), "let-values");
+    }
+
+    #[test]
+    fn test_uninterned() {
+        let x = Symbol::new("x");
+        let count = SYMBOLS.with(|table| table.borrow().names.len());
+        let (a, b) = (Symbol::gensym("x"), Symbol::gensym("x"));
+        assert_ne!(a, b);
+        assert_ne!(a, x);
+        assert_eq!(a.name(), "x");
+        assert!(a.to_string().starts_with("#:x"));
+        let alias = x.alias(new_mark());
+        assert_ne!(alias, x);
+        assert_eq!(alias.original(), x);
+        assert_eq!(a.original(), a);
+        assert!(known::LET.is_known() && !x.is_known() && !known::LET.alias(1).is_known());
+        assert_eq!(SYMBOLS.with(|table| table.borrow().names.len()), count);
+        let mut map = SymbolMap::default();
+        map.insert(a, 1);
+        assert_eq!(map.get(&x), None);
+    }
+}
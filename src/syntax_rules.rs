@@ -0,0 +1,539 @@
+use std::collections::HashMap;
+
+use crate::{
+    error::Exception,
+    symbol::{known, new_mark, Symbol},
+    value::Value,
+};
+
+/// A transformer built from `(syntax-rules (literal ...) (pattern template) ...)`.
+///
+/// Expansion is hygienic. Every identifier a template introduces is renamed to
+/// an alias with a mark of its own for each expansion, so whatever the
+/// expansion binds can never capture the variables of the code passed to the
+/// macro. An alias that nothing in scope binds is a free identifier of the
+/// template, which the evaluator looks up under its original name where the
+/// macro was defined, so the code around a use cannot capture those either.
+#[derive(Debug, PartialEq)]
+pub struct SyntaxRules {
+    pub name: String,
+    literals: Vec<Symbol>,
+    rules: Vec<(Vec<Value>, Value)>,
+}
+
+#[derive(Clone)]
+enum Binding {
+    One(Value),
+    Many(Vec<Binding>),
+}
+
+type Bindings = HashMap<Symbol, Binding>;
+
+impl SyntaxRules {
+    pub fn new(name: &str, spec: &Value) -> Result<Self, Exception> {
+        let list = match spec {
+            Value::List(l) if matches!(l.first(), Some(Value::Symbol(known::SYNTAX_RULES))) => l,
+            _ => {
+                return Err(Exception::new(
+                    "syntax-error",
+                    format!("Expected syntax-rules, got {}", spec),
+                ))
+            }
+        };
+        let literals = match list.get(1) {
+            Some(Value::List(literals)) => literals
+                .iter()
+                .map(|literal| match literal {
+                    Value::Symbol(s) => Ok(*s),
+                    _ => Err(Exception::new(
+                        "syntax-error",
+                        format!("Invalid syntax-rules literal {}", literal),
+                    )),
+                })
+                .collect::<Result<Vec<Symbol>, Exception>>()?,
+            _ => {
+                return Err(Exception::new(
+                    "syntax-error",
+                    "syntax-rules expects a list of literals",
+                ))
+            }
+        };
+        let mut rules = vec![];
+        for rule in &list[2..] {
+            match rule {
+                Value::List(r) => match r.as_slice() {
+                    [Value::List(pattern), template] if !pattern.is_empty() => {
+                        rules.push((pattern.to_vec(), template.clone()))
+                    }
+                    _ => {
+                        return Err(Exception::new(
+                            "syntax-error",
+                            format!("Invalid syntax-rules rule {}", rule),
+                        ))
+                    }
+                },
+                _ => {
+                    return Err(Exception::new(
+                        "syntax-error",
+                        format!("Invalid syntax-rules rule {}", rule),
+                    ))
+                }
+            }
+        }
+        Ok(SyntaxRules {
+            name: name.to_string(),
+            literals,
+            rules,
+        })
+    }
+
+    /// Rewrites `form` with the template of the first rule whose pattern matches.
+    pub fn expand(&self, form: &Value) -> Result<Value, Exception> {
+        let args = match form {
+            Value::List(l) if !l.is_empty() => &l[1..],
+            _ => {
+                return Err(Exception::new(
+                    "syntax-error",
+                    format!("Invalid use of {}", self.name),
+                ))
+            }
+        };
+        for (pattern, template) in &self.rules {
+            let mut bindings = Bindings::new();
+            // The keyword position of the pattern is never matched.
+            if self.match_list(&pattern[1..], args, &mut bindings) {
+                let mark = new_mark();
+                let expansion = instantiate(template, &bindings, mark)?;
+                return Ok(unmark_data(&expansion, mark, 0, false));
+            }
+        }
+        Err(Exception::new(
+            "syntax-error",
+            format!("No rule of {} matches {}", self.name, form),
+        ))
+    }
+
+    fn match_pattern(&self, pattern: &Value, form: &Value, bindings: &mut Bindings) -> bool {
+        match pattern {
+            Value::Symbol(known::WILDCARD) => true,
+            // An alias of a literal from another expansion matches it too.
+            Value::Symbol(s) if self.literals.contains(s) => {
+                matches!(form, Value::Symbol(f) if f.original() == *s)
+            }
+            Value::Symbol(s) => {
+                bindings.insert(*s, Binding::One(form.clone()));
+                true
+            }
+            Value::List(patterns) => match form {
+                Value::List(forms) => self.match_list(patterns, forms, bindings),
+                _ => false,
+            },
+            _ => pattern == form,
+        }
+    }
+
+    /// Matches a list pattern where one element may be followed by `...`, in
+    /// which case it matches as many forms as the other elements leave over.
+    fn match_list(&self, patterns: &[Value], forms: &[Value], bindings: &mut Bindings) -> bool {
+        let at = match patterns.iter().position(is_ellipsis) {
+            Some(0) => return false,
+            Some(at) => at,
+            None => {
+                return patterns.len() == forms.len()
+                    && patterns
+                        .iter()
+                        .zip(forms)
+                        .all(|(p, f)| self.match_pattern(p, f, bindings))
+            }
+        };
+        let before = &patterns[..at - 1];
+        let repeated = &patterns[at - 1];
+        let after = &patterns[at + 1..];
+        if forms.len() < before.len() + after.len() {
+            return false;
+        }
+        let (forms_before, rest) = forms.split_at(before.len());
+        let (forms_repeated, forms_after) = rest.split_at(rest.len() - after.len());
+        if !self.match_list(before, forms_before, bindings)
+            || !self.match_list(after, forms_after, bindings)
+        {
+            return false;
+        }
+        let mut matches = vec![];
+        for form in forms_repeated {
+            let mut m = Bindings::new();
+            if !self.match_pattern(repeated, form, &mut m) {
+                return false;
+            }
+            matches.push(m);
+        }
+        for var in self.pattern_vars(repeated) {
+            let sequence = matches.iter_mut().filter_map(|m| m.remove(&var)).collect();
+            bindings.insert(var, Binding::Many(sequence));
+        }
+        true
+    }
+
+    fn pattern_vars(&self, pattern: &Value) -> Vec<Symbol> {
+        match pattern {
+            Value::Symbol(s)
+                if ![known::WILDCARD, known::ELLIPSIS].contains(s)
+                    && !self.literals.contains(s) =>
+            {
+                vec![*s]
+            }
+            Value::List(l) => l.iter().flat_map(|p| self.pattern_vars(p)).collect(),
+            _ => vec![],
+        }
+    }
+}
+
+/// Fills in the template. Every symbol that is not a pattern variable becomes
+/// an alias with the mark of this expansion. The special forms and the other
+/// known names are left alone: they mean the same wherever they appear.
+fn instantiate(template: &Value, bindings: &Bindings, mark: u32) -> Result<Value, Exception> {
+    match template {
+        Value::Symbol(s) => match bindings.get(s) {
+            Some(Binding::One(v)) => Ok(v.clone()),
+            Some(Binding::Many(_)) => Err(Exception::new(
+                "syntax-error",
+                format!("{} must be followed by ...", s),
+            )),
+            None if s.is_known() => Ok(template.clone()),
+            None => Ok(Value::Symbol(s.alias(mark))),
+        },
+        Value::List(templates) => {
+            let mut result = vec![];
+            let mut i = 0;
+            while i < templates.len() {
+                if templates.get(i + 1).is_some_and(is_ellipsis) {
+                    for b in repetitions(&templates[i], bindings)? {
+                        result.push(instantiate(&templates[i], &b, mark)?);
+                    }
+                    i += 2;
+                } else {
+                    result.push(instantiate(&templates[i], bindings, mark)?);
+                    i += 1;
+                }
+            }
+            Ok(Value::List(result.into()))
+        }
+        _ => Ok(template.clone()),
+    }
+}
+
+/// Gives the aliases of this expansion in quoted data, the literal parts of
+/// quasiquotes and `case` datums their original names back. Data is never a
+/// binding or a reference, and a quoted symbol should read as written.
+fn unmark_data(node: &Value, mark: u32, quasi: usize, quoted: bool) -> Value {
+    let list = match node {
+        Value::Symbol(s) if quoted && *s == s.original().alias(mark) => {
+            return Value::Symbol(s.original())
+        }
+        Value::List(l) => l,
+        _ => return node.clone(),
+    };
+    let each = |items: &[Value], quoted: bool| {
+        items
+            .iter()
+            .map(|item| unmark_data(item, mark, quasi, quoted))
+            .collect::<Vec<_>>()
+    };
+    let items = match list.as_slice() {
+        [Value::Symbol(known::QUOTE), datum] if quasi == 0 => {
+            vec![list[0].clone(), unmark_data(datum, mark, 0, true)]
+        }
+        [Value::Symbol(known::QUASIQUOTE), x] => {
+            vec![list[0].clone(), unmark_data(x, mark, quasi + 1, true)]
+        }
+        [Value::Symbol(known::UNQUOTE | known::UNQUOTE_SPLICING), x] if quasi > 0 => {
+            vec![list[0].clone(), unmark_data(x, mark, quasi - 1, quasi > 1)]
+        }
+        [Value::Symbol(known::CASE), key, clauses @ ..] if !quoted => {
+            let mut items = vec![list[0].clone(), unmark_data(key, mark, quasi, false)];
+            items.extend(clauses.iter().map(|clause| match clause {
+                Value::List(c) if !c.is_empty() => {
+                    let mut clause = vec![unmark_data(&c[0], mark, quasi, true)];
+                    clause.extend(each(&c[1..], false));
+                    Value::List(clause.into())
+                }
+                _ => clause.clone(),
+            }));
+            items
+        }
+        _ => each(list, quoted),
+    };
+    Value::List(items.into())
+}
+
+fn is_ellipsis(node: &Value) -> bool {
+    matches!(node, Value::Symbol(known::ELLIPSIS))
+}
+
+/// The bindings to instantiate `template` with for every repetition of a
+/// `template ...`, one for each element of the sequences it refers to.
+fn repetitions(template: &Value, bindings: &Bindings) -> Result<Vec<Bindings>, Exception> {
+    let mut vars = vec![];
+    template_vars(template, &mut vars);
+    let sequences = vars
+        .iter()
+        .filter_map(|var| match bindings.get(var) {
+            Some(Binding::Many(sequence)) => Some((var, sequence)),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    let len = match sequences.first() {
+        Some((_, sequence)) => sequence.len(),
+        None => {
+            return Err(Exception::new(
+                "syntax-error",
+                format!("No pattern variable to repeat in {}", template),
+            ))
+        }
+    };
+    if sequences.iter().any(|(_, sequence)| sequence.len() != len) {
+        return Err(Exception::new(
+            "syntax-error",
+            format!(
+                "Pattern variables in {} repeat a different number of times",
+                template
+            ),
+        ));
+    }
+    Ok((0..len)
+        .map(|i| {
+            let mut b = bindings.clone();
+            for (var, sequence) in &sequences {
+                b.insert(**var, sequence[i].clone());
+            }
+            b
+        })
+        .collect())
+}
+
+fn template_vars(template: &Value, vars: &mut Vec<Symbol>) {
+    match template {
+        Value::Symbol(s) => vars.push(*s),
+        Value::List(l) => l.iter().for_each(|t| template_vars(t, vars)),
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, rc::Rc};
+
+    use crate::{environment::Environment, program::evaluate};
+
+    use super::*;
+
+    fn eval(source: &str) -> Result<Value, Exception> {
+        let mut env = Rc::new(RefCell::new(Environment::new()));
+        evaluate(source, &mut env)
+    }
+
+    fn numbers(ns: &[f64]) -> Value {
+        Value::List(ns.iter().map(|n| Value::Number(*n)).collect())
+    }
+
+    #[test]
+    fn test_simple_rule() {
+        let source = "(define-syntax my-unless
+                        (syntax-rules ()
+                          ((_ test body ...) (if test 0 (and body ...)))))
+                      (my-unless (gt 1 2) 1 2)";
+        let res = eval(source).unwrap();
+        assert_eq!(res, numbers(&[2.0]));
+    }
+
+    #[test]
+    fn test_introduced_binding_does_not_capture() {
+        let source = "(define-syntax my-or
+                        (syntax-rules ()
+                          ((_) 0)
+                          ((_ e) e)
+                          ((_ e r ...) (let ((t e)) (if t t (my-or r ...))))))
+                      (define t 5)
+                      (my-or 0 t)";
+        let res = eval(source).unwrap();
+        assert_eq!(res, numbers(&[5.0]));
+    }
+
+    #[test]
+    fn test_use_site_binding_does_not_capture() {
+        let source = "(define-syntax two
+                        (syntax-rules ()
+                          ((_ x) (list x x))))
+                      (let ((list +)) (two 1))";
+        let res = eval(source).unwrap();
+        assert_eq!(res, Value::List(vec![numbers(&[1.0, 1.0])].into()));
+        let source = "(define count 0)
+                      (define-syntax bump!
+                        (syntax-rules ()
+                          ((_) (set! count (+ count 1)))))
+                      (let ((count 10)) (bump!) count)
+                      count";
+        let res = eval(source).unwrap();
+        assert_eq!(res, numbers(&[10.0, 1.0]));
+    }
+
+    #[test]
+    fn test_no_binding_form_captures() {
+        // Each macro binds `x` around the expression passed to it, which must
+        // still see the caller's `x`.
+        let binders = [
+            "(let ((x 1)) e)",
+            "(let* ((x 1)) e)",
+            "(letrec ((x 1)) e)",
+            "(let x ((n 0)) e)",
+            "((fn (x) e) 1)",
+            "((fn (&rest x) e) 1)",
+            "((fn (&key x) e) :x 1)",
+            "((fn ((x)) e) '(1))",
+            "(let () (define x 1) e)",
+            "(let ((r 0)) (dotimes (x 1) (set! r e)) r)",
+            "(let ((r 0)) (dolist (x '(1)) (set! r e)) r)",
+            "(receive (x) (values 1) e)",
+            "(let-values (((x) (values 1))) e)",
+            "(match 1 (x e))",
+            "(match '(1) ((x) e))",
+            "(try (error \"boom\") (catch x e))",
+            "(try (handler-bind ((error (fn (x) (raise e)))) (error \"boom\")) (catch c c))",
+            "(restart-case (invoke-restart 'r 1) (r (x) e))",
+            "(reset (shift x e))",
+        ];
+        for binder in binders {
+            let source = format!(
+                "(define-syntax m (syntax-rules () ((_ e) {})))
+                 (let ((x 42)) (m x))",
+                binder
+            );
+            assert_eq!(eval(&source).unwrap(), numbers(&[42.0]), "{}", binder);
+        }
+    }
+
+    #[test]
+    fn test_captures_reported_in_review() {
+        let source = "(define-syntax rep
+                        (syntax-rules ()
+                          ((_ e) (let ((acc '())) (dotimes (j 3) (set! acc (cons e acc))) acc))))
+                      (let ((j 100)) (rep j))";
+        assert_eq!(eval(source).unwrap().to_string(), "((100 100 100))");
+        let source = "(define-syntax with-y
+                        (syntax-rules ()
+                          ((_ e) (let-values (((y) (values 1))) e))))
+                      (let ((y 42)) (with-y y))";
+        assert_eq!(eval(source).unwrap(), numbers(&[42.0]));
+        let source = "(define-syntax m2
+                        (syntax-rules ()
+                          ((_ a b) (receive (z) (values a) b))))
+                      (let ((z 5)) (m2 1 z))";
+        assert_eq!(eval(source).unwrap(), numbers(&[5.0]));
+    }
+
+    #[test]
+    fn test_free_identifiers_do_not_depend_on_globals() {
+        // `helper` does not exist yet when the macro is defined or expanded.
+        let source = "(define-syntax call-helper
+                        (syntax-rules ()
+                          ((_) (helper))))
+                      (define f (fn () (let ((helper (fn () 2))) (call-helper))))
+                      (define helper (fn () 1))
+                      (f)";
+        assert_eq!(eval(source).unwrap(), numbers(&[1.0]));
+        let source = "(define-syntax counter
+                        (syntax-rules ()
+                          ((_) (let ((n 0)) (fn () (set! n (+ n 1)) n)))))
+                      (define c (counter))
+                      (c)
+                      (let ((n 10)) (c))";
+        assert_eq!(eval(source).unwrap(), numbers(&[1.0, 2.0]));
+    }
+
+    #[test]
+    fn test_quoted_and_case_data_keep_their_names() {
+        let source = "(define-syntax kind
+                        (syntax-rules ()
+                          ((_ e) (case e ((a) 'is-a) (else `(not-a ,e))))))
+                      (list (kind 'a) (kind 'b))";
+        assert_eq!(eval(source).unwrap().to_string(), "((is-a (not-a b)))");
+        let source = "(define-syntax retry
+                        (syntax-rules ()
+                          ((_ e) (restart-case e (use-value (v) v)))))
+                      (handler-bind ((error (fn (c) (invoke-restart 'use-value 7))))
+                        (retry (error \"boom\")))";
+        assert_eq!(eval(source).unwrap(), numbers(&[7.0]));
+    }
+
+    #[test]
+    fn test_swap() {
+        let source = "(define-syntax swap!
+                        (syntax-rules ()
+                          ((_ a b) (let ((tmp a)) (set! a b) (set! b tmp)))))
+                      (define tmp 1)
+                      (define other 2)
+                      (swap! tmp other)
+                      (list tmp other)";
+        let res = eval(source).unwrap();
+        assert_eq!(res, Value::List(vec![numbers(&[2.0, 1.0])].into()));
+    }
+
+    #[test]
+    fn test_recursive_macro() {
+        let source = "(define-syntax my-let*
+                        (syntax-rules ()
+                          ((_ () body ...) (let () body ...))
+                          ((_ ((x v) rest ...) body ...)
+                           (let ((x v)) (my-let* (rest ...) body ...)))))
+                      (my-let* ((a 1) (b (+ a 1))) (* a b 10))";
+        let res = eval(source).unwrap();
+        assert_eq!(res, numbers(&[20.0]));
+    }
+
+    #[test]
+    fn test_nested_ellipsis() {
+        let source = "(define-syntax sums
+                        (syntax-rules ()
+                          ((_ (a ...) ...) (list (+ 0 0 a ...) ...))))
+                      (sums (1 2) (3 4 5) ())";
+        let res = eval(source).unwrap();
+        assert_eq!(res, Value::List(vec![numbers(&[3.0, 12.0, 0.0])].into()));
+    }
+
+    #[test]
+    fn test_literals() {
+        let source = "(define-syntax for
+                        (syntax-rules (in)
+                          ((_ x in xs body ...) (map (fn (x) body ...) xs))))
+                      (for y in (list 1 2) (* y 10))";
+        let res = eval(source).unwrap();
+        assert_eq!(res, Value::List(vec![numbers(&[10.0, 20.0])].into()));
+        let source = "(define-syntax for
+                        (syntax-rules (in)
+                          ((_ x in xs body ...) (map (fn (x) body ...) xs))))
+                      (for y on (list 1 2) (* y 10))";
+        assert!(eval(source).is_err());
+    }
+
+    #[test]
+    fn test_quoted_template_symbols() {
+        let source = "(define-syntax name
+                        (syntax-rules ()
+                          ((_) (let ((x 1)) 'x))))
+                      (name)";
+        let res = eval(source).unwrap();
+        assert_eq!(
+            res,
+            Value::List(vec![Value::Symbol(Symbol::new("x"))].into())
+        );
+    }
+
+    #[test]
+    fn test_no_matching_rule() {
+        let source = "(define-syntax two
+                        (syntax-rules ()
+                          ((_ a b) (list a b))))
+                      (two 1)";
+        assert!(eval(source).is_err());
+    }
+}
@@ -1,12 +1,140 @@
-use std::fmt;
+use std::{fmt, rc::Rc};
 
-#[derive(Clone, Debug, PartialEq)]
+use crate::{
+    environment::Env,
+    error::{ErrorObject, Exception},
+    generator::Generator,
+    keyword::Keyword,
+    lexer::char_literal,
+    list::List,
+    map::Map,
+    pair::{self, Pair},
+    program::{Continuation, Tail},
+    promise::Promise,
+    record::{Record, RecordType},
+    symbol::Symbol,
+    syntax_rules::SyntaxRules,
+    vector::Vector,
+};
+
+#[derive(Clone, Debug)]
 pub enum Value {
     Number(f64),
-    Symbol(String),
-    List(Vec<Value>),
+    Symbol(Symbol),
+    String(String),
+    Char(char),
+    Keyword(Keyword),
+    List(List),
     Nil,
-    Lambda(Vec<String>, Vec<Value>),
+    Lambda(Vec<Symbol>, Vec<Value>, Env),
+    Builtin(Builtin),
+    Macro(Box<Value>),
+    SyntaxRules(Rc<SyntaxRules>),
+    Error(Rc<ErrorObject>),
+    Continuation(Rc<Continuation>),
+    Generator(Rc<Generator>),
+    Promise(Rc<Promise>),
+    Map(Rc<Map>),
+    Vector(Vector<Value>),
+    Pair(Rc<Pair>),
+    Record(Rc<Record>),
+    RecordType(Rc<RecordType>),
+    /// What `values` returns with other than one argument. Only forms that
+    /// ask for all of them see more than the first.
+    Values(Rc<[Value]>),
+}
+
+/// Lists are equal to lists with the same items whether they are made of
+/// pairs or not.
+impl PartialEq for Value {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Value::Number(a), Value::Number(b)) => a == b,
+            (Value::Symbol(a), Value::Symbol(b)) => a == b,
+            (Value::String(a), Value::String(b)) => a == b,
+            (Value::Char(a), Value::Char(b)) => a == b,
+            (Value::Keyword(a), Value::Keyword(b)) => a == b,
+            (Value::Nil, Value::Nil) => true,
+            (Value::Lambda(a, b, c), Value::Lambda(x, y, z)) => a == x && b == y && c == z,
+            (Value::Builtin(a), Value::Builtin(b)) => a == b,
+            (Value::Macro(a), Value::Macro(b)) => a == b,
+            (Value::SyntaxRules(a), Value::SyntaxRules(b)) => a == b,
+            (Value::Error(a), Value::Error(b)) => a == b,
+            (Value::Continuation(a), Value::Continuation(b)) => a == b,
+            (Value::Generator(a), Value::Generator(b)) => a == b,
+            (Value::Promise(a), Value::Promise(b)) => a == b,
+            (Value::Map(a), Value::Map(b)) => a == b,
+            (Value::Vector(a), Value::Vector(b)) => a == b,
+            (Value::Record(a), Value::Record(b)) => a == b,
+            (Value::RecordType(a), Value::RecordType(b)) => Rc::ptr_eq(a, b),
+            (Value::Values(a), Value::Values(b)) => a == b,
+            (Value::List(_) | Value::Pair(_), Value::List(_) | Value::Pair(_)) => {
+                pair::lists_eq(self, other)
+            }
+            _ => false,
+        }
+    }
+}
+
+pub type BuiltinFn = fn(&[Value]) -> Result<Value, Exception>;
+
+/// A builtin that hands control back to the evaluator, to call functions or to
+/// capture the continuation.
+pub type ControlFn = fn(&[Value]) -> Result<Tail, Exception>;
+
+#[derive(Clone, Copy)]
+pub enum Primitive {
+    Value(BuiltinFn),
+    Control(ControlFn),
+}
+
+/// A function implemented in Rust, looked up by name in `builtins`.
+#[derive(Clone, Copy)]
+pub struct Builtin {
+    pub name: &'static str,
+    pub func: Primitive,
+}
+
+impl PartialEq for Builtin {
+    fn eq(&self, other: &Self) -> bool {
+        self.name == other.name
+    }
+}
+
+impl fmt::Debug for Builtin {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Builtin({})", self.name)
+    }
+}
+
+impl Value {
+    /// Only `nil` and `0` are false, everything else counts as true.
+    pub fn is_truthy(&self) -> bool {
+        !matches!(self, Value::Nil | Value::Number(0.0))
+    }
+
+    /// The value a context that takes one value sees: the first of multiple
+    /// values, or `nil` if there are none.
+    pub fn single(self) -> Value {
+        match self {
+            Value::Values(vals) => vals.first().cloned().unwrap_or(Value::Nil),
+            val => val,
+        }
+    }
+
+    /// All the values, one for anything but multiple values.
+    pub fn spread(self) -> Vec<Value> {
+        match self {
+            Value::Values(vals) => vals.to_vec(),
+            val => vec![val],
+        }
+    }
+}
+
+impl From<bool> for Value {
+    fn from(b: bool) -> Self {
+        Value::Number(if b { 1.0 } else { 0.0 })
+    }
 }
 
 impl fmt::Display for Value {
@@ -14,6 +142,9 @@ impl fmt::Display for Value {
         match self {
             Value::Number(n) => write!(f, "{}", n),
             Value::Symbol(s) => write!(f, "{}", s),
+            Value::String(s) => write!(f, "{:?}", s),
+            Value::Char(c) => write!(f, "{}", char_literal(*c)),
+            Value::Keyword(k) => write!(f, "{}", k),
             Value::List(l) => {
                 write!(f, "(")?;
                 for (i, node) in l.iter().enumerate() {
@@ -25,17 +156,60 @@ impl fmt::Display for Value {
                 write!(f, ")")
             }
             Value::Nil => write!(f, "nil"),
-            Value::Lambda(args, body) => {
+            Value::Lambda(args, body, _) => {
                 write!(f, "fn(")?;
                 for arg in args {
                     write!(f, "{} ", arg)?;
                 }
                 write!(f, ")")?;
                 for expr in body {
-                write!(f, " {}", expr)?;
+                    write!(f, " {}", expr)?;
+                }
+                Ok(())
+            }
+            Value::Builtin(b) => write!(f, "#<builtin {}>", b.name),
+            Value::Macro(expander) => write!(f, "macro {}", expander),
+            Value::SyntaxRules(rules) => write!(f, "#<syntax {}>", rules.name),
+            Value::Error(e) => write!(f, "#<{} {:?}>", e.kind, e.message),
+            Value::Continuation(_) => write!(f, "#<continuation>"),
+            Value::Generator(_) => write!(f, "#<generator>"),
+            Value::Promise(_) => write!(f, "#<promise>"),
+            Value::Record(r) => write!(f, "{}", r),
+            Value::RecordType(t) => write!(f, "#<record-type {}>", t.display_name()),
+            Value::Values(vals) => {
+                for (i, val) in vals.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, " ")?;
+                    }
+                    write!(f, "{}", val)?;
                 }
                 Ok(())
             }
+            Value::Map(m) => {
+                write!(f, "{{")?;
+                for (i, (key, val)) in m.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, " ")?;
+                    }
+                    write!(f, "{} {}", key, val)?;
+                }
+                write!(f, "}}")
+            }
+            Value::Pair(p) => {
+                write!(f, "({}", p.car.borrow())?;
+                pair::write_rest(f, p.cdr.borrow().clone())?;
+                write!(f, ")")
+            }
+            Value::Vector(v) => {
+                write!(f, "[")?;
+                for (i, item) in v.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, " ")?;
+                    }
+                    write!(f, "{}", item)?;
+                }
+                write!(f, "]")
+            }
         }
     }
 }
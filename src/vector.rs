@@ -0,0 +1,271 @@
+use std::rc::Rc;
+
+const BITS: usize = 5;
+const WIDTH: usize = 1 << BITS;
+const MASK: usize = WIDTH - 1;
+
+/// A persistent vector: a tree of nodes with 32 children each, and a tail that
+/// holds the last up to 32 items. Setting or pushing an item copies only the
+/// path to it, the rest of the tree is shared with the old vector.
+pub struct Vector<T> {
+    len: usize,
+    shift: usize,
+    root: Rc<Node<T>>,
+    tail: Rc<Vec<T>>,
+}
+
+enum Node<T> {
+    Branch(Vec<Rc<Node<T>>>),
+    Leaf(Vec<T>),
+}
+
+impl<T: Clone> Vector<T> {
+    pub fn new() -> Self {
+        Vector {
+            len: 0,
+            shift: BITS,
+            root: Rc::new(Node::Branch(vec![])),
+            tail: Rc::new(vec![]),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// The index of the first item in the tail.
+    fn tail_offset(&self) -> usize {
+        if self.len < WIDTH {
+            0
+        } else {
+            ((self.len - 1) >> BITS) << BITS
+        }
+    }
+
+    pub fn get(&self, i: usize) -> Option<&T> {
+        if i >= self.len {
+            return None;
+        }
+        if i >= self.tail_offset() {
+            return self.tail.get(i - self.tail_offset());
+        }
+        let mut node = &self.root;
+        let mut level = self.shift;
+        loop {
+            match node.as_ref() {
+                Node::Branch(children) => node = &children[(i >> level) & MASK],
+                Node::Leaf(items) => return items.get(i & MASK),
+            }
+            level -= BITS;
+        }
+    }
+
+    #[must_use]
+    pub fn push(&self, val: T) -> Self {
+        if self.len - self.tail_offset() < WIDTH {
+            let mut tail = self.tail.as_ref().clone();
+            tail.push(val);
+            return Vector {
+                len: self.len + 1,
+                shift: self.shift,
+                root: self.root.clone(),
+                tail: Rc::new(tail),
+            };
+        }
+        let leaf = Rc::new(Node::Leaf(self.tail.as_ref().clone()));
+        let (root, shift) = if (self.len >> BITS) > (1 << self.shift) {
+            let path = new_path(self.shift, leaf);
+            let root = Node::Branch(vec![self.root.clone(), path]);
+            (Rc::new(root), self.shift + BITS)
+        } else {
+            (self.push_leaf(self.shift, &self.root, leaf), self.shift)
+        };
+        Vector {
+            len: self.len + 1,
+            shift,
+            root,
+            tail: Rc::new(vec![val]),
+        }
+    }
+
+    fn push_leaf(&self, level: usize, parent: &Rc<Node<T>>, leaf: Rc<Node<T>>) -> Rc<Node<T>> {
+        let mut children = match parent.as_ref() {
+            Node::Branch(children) => children.clone(),
+            Node::Leaf(_) => unreachable!("leaves are only at level 0"),
+        };
+        let i = ((self.len - 1) >> level) & MASK;
+        if level == BITS {
+            children.push(leaf);
+        } else if let Some(child) = children.get(i) {
+            children[i] = self.push_leaf(level - BITS, child, leaf);
+        } else {
+            children.push(new_path(level - BITS, leaf));
+        }
+        Rc::new(Node::Branch(children))
+    }
+
+    /// Returns a vector with the item at `i` replaced, or `None` if `i` is out of
+    /// bounds.
+    #[must_use]
+    pub fn set(&self, i: usize, val: T) -> Option<Self> {
+        if i >= self.len {
+            return None;
+        }
+        let mut vector = Vector {
+            len: self.len,
+            shift: self.shift,
+            root: self.root.clone(),
+            tail: self.tail.clone(),
+        };
+        if i >= self.tail_offset() {
+            Rc::make_mut(&mut vector.tail)[i - self.tail_offset()] = val;
+        } else {
+            vector.root = set_in(self.shift, &self.root, i, val);
+        }
+        Some(vector)
+    }
+
+    /// Walks the leaves of the tree in order, then the tail.
+    pub fn iter(&self) -> Iter<'_, T> {
+        let root = match self.root.as_ref() {
+            Node::Branch(children) => children.iter(),
+            Node::Leaf(_) => unreachable!("the root is a branch"),
+        };
+        Iter {
+            branches: vec![root],
+            items: [].iter(),
+            tail: Some(&self.tail),
+        }
+    }
+}
+
+pub struct Iter<'a, T> {
+    branches: Vec<std::slice::Iter<'a, Rc<Node<T>>>>,
+    items: std::slice::Iter<'a, T>,
+    tail: Option<&'a [T]>,
+}
+
+impl<'a, T> Iterator for Iter<'a, T> {
+    type Item = &'a T;
+
+    fn next(&mut self) -> Option<&'a T> {
+        loop {
+            if let Some(item) = self.items.next() {
+                return Some(item);
+            }
+            let children = match self.branches.last_mut() {
+                Some(children) => children,
+                None => {
+                    self.items = self.tail.take()?.iter();
+                    continue;
+                }
+            };
+            match children.next().map(Rc::as_ref) {
+                Some(Node::Branch(children)) => self.branches.push(children.iter()),
+                Some(Node::Leaf(items)) => self.items = items.iter(),
+                None => {
+                    self.branches.pop();
+                }
+            }
+        }
+    }
+}
+
+fn new_path<T>(level: usize, leaf: Rc<Node<T>>) -> Rc<Node<T>> {
+    if level == 0 {
+        leaf
+    } else {
+        Rc::new(Node::Branch(vec![new_path(level - BITS, leaf)]))
+    }
+}
+
+fn set_in<T: Clone>(level: usize, node: &Rc<Node<T>>, i: usize, val: T) -> Rc<Node<T>> {
+    match node.as_ref() {
+        Node::Leaf(items) => {
+            let mut items = items.clone();
+            items[i & MASK] = val;
+            Rc::new(Node::Leaf(items))
+        }
+        Node::Branch(children) => {
+            let mut children = children.clone();
+            let child = (i >> level) & MASK;
+            children[child] = set_in(level - BITS, &children[child], i, val);
+            Rc::new(Node::Branch(children))
+        }
+    }
+}
+
+impl<T> Clone for Vector<T> {
+    fn clone(&self) -> Self {
+        Vector {
+            len: self.len,
+            shift: self.shift,
+            root: self.root.clone(),
+            tail: self.tail.clone(),
+        }
+    }
+}
+
+impl<T: Clone> Default for Vector<T> {
+    fn default() -> Self {
+        Vector::new()
+    }
+}
+
+impl<T: Clone> From<Vec<T>> for Vector<T> {
+    fn from(items: Vec<T>) -> Self {
+        items.into_iter().collect()
+    }
+}
+
+impl<T: Clone> FromIterator<T> for Vector<T> {
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+        iter.into_iter().fold(Vector::new(), |v, val| v.push(val))
+    }
+}
+
+impl<T: Clone + PartialEq> PartialEq for Vector<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.len == other.len && self.iter().eq(other.iter())
+    }
+}
+
+impl<T: Clone + std::fmt::Debug> std::fmt::Debug for Vector<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_push_and_get() {
+        let n = 40_000;
+        let v = (0..n).collect::<Vector<_>>();
+        assert_eq!(v.len(), n);
+        assert!((0..n).all(|i| v.get(i) == Some(&i)));
+        assert_eq!(v.get(n), None);
+        assert!(v.iter().copied().eq(0..n));
+        assert!(Vector::<usize>::new().iter().next().is_none());
+    }
+
+    #[test]
+    fn test_set_shares_the_rest() {
+        let v = (0..1100).collect::<Vector<_>>();
+        let w = v.set(5, 0).unwrap().set(1090, 0).unwrap().push(7);
+        assert_eq!(v.get(5), Some(&5));
+        assert_eq!(v.get(1090), Some(&1090));
+        assert_eq!(v.len(), 1100);
+        assert_eq!(w.get(5), Some(&0));
+        assert_eq!(w.get(1090), Some(&0));
+        assert_eq!(w.get(1100), Some(&7));
+        assert!(v.set(1100, 0).is_none());
+        assert!(w.iter().copied().eq((0..1101).map(|i| match i {
+            5 | 1090 => 0,
+            1100 => 7,
+            i => i,
+        })));
+    }
+}